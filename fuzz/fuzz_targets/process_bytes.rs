@@ -0,0 +1,19 @@
+#![no_main]
+use accounting::{Amount, Engine};
+use libfuzzer_sys::fuzz_target;
+
+// Processing arbitrary input must only produce warnings or errors, never panic
+fuzz_target!(|data: &[u8]| {
+    let mut engine = Engine::new();
+    // Limits off, so balances can reach the edges of amount range
+    engine.config.max_amount = Amount::from_raw(i64::MAX);
+    engine.config.allow_adjustments = true;
+    if engine.process_bytes(data).is_ok() {
+        let _ = engine.verify_conservation();
+        let _ = engine.totals();
+        for user in engine.users() {
+            let _ = user.recompute_account();
+        }
+        let _ = engine.report().to_csv_string();
+    }
+});
@@ -0,0 +1,148 @@
+use crate::user::{Amount, DecimalSeparator, RoundingMode, TransactionType, AMOUNT_DECIMALS};
+use std::collections::HashSet;
+
+/// Rules applied while processing transactions
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Allow unfreezing accounts locked by chargeback
+    pub allow_unfreeze: bool,
+    /// Lock account when its transaction is charged back
+    pub freeze_on_chargeback: bool,
+    /// Allow disputing transaction again after its dispute was resolved
+    pub allow_redispute: bool,
+    /// Largest accepted deposit or withdrawal amount
+    pub max_amount: Amount,
+    /// Smallest accepted deposit or withdrawal amount, zero is always rejected
+    pub min_amount: Amount,
+    /// Forget charged back transactions, as they can't be disputed again
+    pub evict_chargedback: bool,
+    /// Number of deposits and withdrawals kept per client.
+    /// Oldest are forgotten first, except those under dispute.
+    /// Owners of their ids are still kept by `Ledger`, so memory keeps
+    /// growing by a few bytes per transaction to reject reused ids.
+    pub history_window: Option<usize>,
+    /// Reject dispute, resolve and chargeback requests carrying amount.
+    /// Partial disputes are not possible then.
+    pub strict_amounts: bool,
+    /// Accept fee and interest requests
+    pub allow_adjustments: bool,
+    /// Reject requests with timestamp earlier than last accepted one of the same client
+    pub strict_timestamps: bool,
+    /// Keep deposits and withdrawals rejected by locked account in history,
+    /// marked as rejected and without effect on balances
+    pub record_frozen_rejections: bool,
+    /// Fee charged on top of each withdrawal, in basis points of its amount
+    pub withdrawal_fee_bps: u32,
+    /// Requests of other types are rejected without reaching client account
+    pub allowed_types: HashSet<TransactionType>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            allow_unfreeze: false,
+            freeze_on_chargeback: true,
+            allow_redispute: true,
+            max_amount: Amount::from_int(1_000_000_000),
+            min_amount: Amount::ZERO,
+            evict_chargedback: false,
+            history_window: None,
+            strict_amounts: false,
+            allow_adjustments: false,
+            strict_timestamps: false,
+            record_frozen_rejections: false,
+            withdrawal_fee_bps: 0,
+            allowed_types: TransactionType::ALL.iter().copied().collect(),
+        }
+    }
+}
+
+/// Expected layout of CSV input
+#[derive(Clone, Debug)]
+pub struct InputFormat {
+    /// First record is `type,client,tx,amount` header.
+    /// Otherwise columns are expected in that order without header.
+    pub has_headers: bool,
+    /// Decimal places kept from parsed amounts, at most `AMOUNT_DECIMALS`
+    pub decimals: u32,
+    /// Rounding of amounts with more than `decimals` decimal places
+    pub rounding: RoundingMode,
+    /// Decimal point of amounts. With `Comma` and default delimiter
+    /// amounts have to be quoted, e.g. `"1,50"`.
+    pub decimal_separator: DecimalSeparator,
+    /// Amount column holds integer number of minor units with this many
+    /// decimal places, e.g. 2 for cents
+    pub minor_units: Option<u32>,
+    /// Field separator, e.g. `b';'` or `b'\t'`
+    pub delimiter: u8,
+    /// Rows of these clients are skipped
+    pub ignore_clients: HashSet<u16>,
+    /// When set, rows of other clients are skipped
+    pub only_clients: Option<HashSet<u16>>,
+    /// Disputes, resolves and chargebacks of not yet seen transactions
+    /// are retried after whole input is read.
+    pub retry_unknown: bool,
+    /// Unknown transaction type stops processing with error, instead of
+    /// skipping the row with warning. Rows read before stay applied.
+    pub strict_types: bool,
+    /// First I/O error while reading input stops processing with error.
+    /// Otherwise record being read is skipped with warning and reading continues.
+    /// Input failing repeatedly is treated as ended after the skipped record.
+    pub stop_on_io_error: bool,
+}
+
+impl Default for InputFormat {
+    fn default() -> Self {
+        InputFormat {
+            has_headers: true,
+            decimals: AMOUNT_DECIMALS,
+            rounding: RoundingMode::default(),
+            decimal_separator: DecimalSeparator::default(),
+            minor_units: None,
+            delimiter: b',',
+            ignore_clients: HashSet::new(),
+            only_clients: None,
+            retry_unknown: false,
+            strict_types: false,
+            stop_on_io_error: false,
+        }
+    }
+}
+
+impl InputFormat {
+    /// Checks that parsed amounts can be stored with configured decimal places
+    pub fn validate(&self) -> Result<(), String> {
+        Amount::check_decimals(self.decimals)?;
+        if let Some(decimals) = self.minor_units {
+            Amount::check_decimals(decimals)?;
+        }
+        Ok(())
+    }
+
+    /// Checks if rows of client should be processed
+    pub fn accepts_client(&self, client: u16) -> bool {
+        !self.ignore_clients.contains(&client)
+            && self
+                .only_clients
+                .as_ref()
+                .is_none_or(|only| only.contains(&client))
+    }
+}
+
+/// Layout of produced reports
+#[derive(Clone, Debug)]
+pub struct OutputFormat {
+    /// Decimal places of amounts in CSV report
+    pub precision: usize,
+    /// Omit trailing zeros of amounts, `5` instead of `5.0000`
+    pub trim_trailing_zeros: bool,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat {
+            precision: 4,
+            trim_trailing_zeros: false,
+        }
+    }
+}
@@ -1,165 +1,3558 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
 
+use crate::config::{Config, InputFormat, OutputFormat};
+use crate::error::{ProcessingWarning, TxError, WarningKind};
+use crate::ledger::{IdMap, Ledger};
 use crate::user::*;
 
+/// Expected CSV header
+const HEADER: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// Optional columns which may follow `HEADER`
+const OPTIONAL_COLUMNS: [&str; 2] = ["timestamp", "currency"];
+
+/// Column added to report for accounts with many currencies
+const CURRENCY_COLUMN: &str = "currency";
+
+/// Header of CSV report
+const REPORT_HEADER: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// Header of CSV transactions export
+const EXPORT_HEADER: [&str; 6] = ["client", "tx", "type", "amount", "fee", "state"];
+
+/// Number of requests buffered for each shard worker
+const SHARD_QUEUE_SIZE: usize = 1024;
+
+/// Consecutive failed reads after which rest of input is given up
+const READ_RETRIES: usize = 3;
+
+/// Retries failed reads, as CSV reader doesn't continue after I/O error itself.
+/// First error is kept until checked, so the record being read can be skipped.
+struct RetryReader<R> {
+    inner: R,
+    retry: bool,
+    failed: Option<io::Error>,
+    /// Input kept failing, so it's treated as ended
+    gave_up: bool,
+}
+
+impl<R: io::Read> io::Read for RetryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut failures = 0;
+        while !self.gave_up {
+            match self.inner.read(buf) {
+                Ok(n) => return Ok(n),
+                Err(e) if !self.retry => return Err(e),
+                Err(e) => {
+                    failures += 1;
+                    self.gave_up = failures == READ_RETRIES;
+                    self.failed.get_or_insert(e);
+                }
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Results of shard worker other than its ledger, tagged with request sequence
+#[derive(Default)]
+struct ShardOutput {
+    warnings: Vec<ProcessingWarning>,
+    freezes: Vec<(u64, u16)>,
+    events: Vec<(u64, BalanceEvent)>,
+}
+
+/// Passes requests read by `Engine::process_data_parallel` to shard workers,
+/// checking owners of transaction ids on the way. New id is pending until
+/// its shard reports if the request was recorded, only then the id is taken.
+struct ShardDispatcher<T> {
+    senders: Vec<mpsc::SyncSender<(u64, u64, TransactionRequset)>>,
+    workers: Vec<thread::JoinHandle<T>>,
+    allowed_types: HashSet<TransactionType>,
+    owners: IdMap<u32, u16>,
+    /// Client and request sequence of new ids sent to shards
+    pending: IdMap<u32, (u16, u64)>,
+    /// `(tx, sequence, recorded)` reported by shards
+    outcomes: mpsc::Receiver<(u32, u64, bool)>,
+    /// Sequence number of last dispatched request
+    sequence: u64,
+    /// Requests rejected before reaching shards
+    warnings: Vec<ProcessingWarning>,
+}
+
+impl<T> ShardDispatcher<T> {
+    fn settle(&mut self, (tx, sequence, recorded): (u32, u64, bool)) {
+        if let Some(&(client, claimed_at)) = self.pending.get(&tx) {
+            if claimed_at == sequence {
+                self.pending.remove(&tx);
+                if recorded {
+                    self.owners.insert(tx, client);
+                }
+            }
+        }
+    }
+
+    /// Blocks until shard reports outcome of request which claimed `tx`
+    fn wait(&mut self, tx: u32) {
+        while let Some(&(client, _)) = self.pending.get(&tx) {
+            match self.outcomes.recv_timeout(Duration::from_millis(10)) {
+                Ok(outcome) => self.settle(outcome),
+                Err(_) => assert!(
+                    !self.workers[client as usize % self.workers.len()].is_finished(),
+                    "Shard worker stopped"
+                ),
+            }
+        }
+    }
+
+    /// Checks if deposit or withdrawal with this id was recorded, as `Ledger::knows_tx`
+    fn knows_tx(&mut self, tx: u32) -> bool {
+        self.wait(tx);
+        self.owners.contains_key(&tx)
+    }
+
+    fn dispatch(&mut self, line: u64, request: TransactionRequset) {
+        self.sequence += 1;
+        while let Ok(outcome) = self.outcomes.try_recv() {
+            self.settle(outcome);
+        }
+        // Disallowed request is rejected by shard without claiming its id
+        let creates_tx = Ledger::creates_tx(request.r#type);
+        if self.allowed_types.contains(&request.r#type) {
+            // Only dispute of the same client doesn't depend on the earlier outcome
+            let claimed_by = self.pending.get(&request.tx).map(|&(client, _)| client);
+            if creates_tx || claimed_by != Some(request.client) {
+                self.wait(request.tx);
+            }
+            if let Err(e) = Ledger::check_owner(&self.owners, &request) {
+                self.warnings.push(ProcessingWarning {
+                    line,
+                    kind: WarningKind::Rejected(e),
+                });
+                return;
+            }
+            if creates_tx {
+                self.pending
+                    .insert(request.tx, (request.client, self.sequence));
+            }
+        }
+        // Worker only stops after the sender is dropped
+        let shard = request.client as usize % self.senders.len();
+        self.senders[shard]
+            .send((line, self.sequence, request))
+            .expect("Shard worker stopped");
+    }
+}
+
+/// Request as read from CSV, before amount is converted
+#[derive(Deserialize)]
+struct CsvRecord {
+    r#type: String,
+    /// Ids are read wider than stored, so out of range values get clear error
+    client: i64,
+    tx: i64,
+    amount: Option<String>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    currency: Option<String>,
+}
+
+impl CsvRecord {
+    fn into_request(self, input: &InputFormat) -> Result<TransactionRequset, WarningKind> {
+        let r#type = self
+            .r#type
+            .parse()
+            .map_err(|_| WarningKind::UnknownType(self.r#type.clone()))?;
+        self.convert(r#type, input).map_err(WarningKind::Parse)
+    }
+
+    fn convert(
+        self,
+        r#type: TransactionType,
+        input: &InputFormat,
+    ) -> Result<TransactionRequset, String> {
+        let client = u16::try_from(self.client)
+            .map_err(|_| format!("Client id {} out of range 0..={}", self.client, u16::MAX))?;
+        let tx = u32::try_from(self.tx)
+            .map_err(|_| format!("Transaction id {} out of range 0..={}", self.tx, u32::MAX))?;
+
+        let amount = match (self.amount, input.minor_units) {
+            (None, _) => None,
+            (Some(s), None) => Some(Amount::parse_localized(
+                &s,
+                input.decimals,
+                input.rounding,
+                input.decimal_separator,
+            )?),
+            (Some(s), Some(decimals)) => {
+                let invalid = || format!("Invalid amount in minor units: {:?}", s);
+                let units = s.parse::<i64>().map_err(|_| invalid())?;
+                Some(Amount::from_minor_units(units, decimals).ok_or_else(invalid)?)
+            }
+        };
+
+        let currency = match self.currency.as_deref() {
+            None | Some("") => None,
+            Some(c) => Some(c.parse()?),
+        };
+
+        Ok(TransactionRequset {
+            r#type,
+            client,
+            tx,
+            amount,
+            timestamp: self.timestamp,
+            currency,
+        })
+    }
+}
+
+/// Point in time view of single client account
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    pub client: u16,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    pub freeze_reason: FreezeReason,
+    pub accepted_count: u64,
+    pub rejected_count: u64,
+    /// Peak of held funds
+    pub max_held: Amount,
+    /// Sequence number of request which locked the account
+    pub frozen_at: Option<u64>,
+    /// Currency of balances, `None` for the default one
+    pub currency: Option<Currency>,
+}
+
+impl From<&User> for AccountSnapshot {
+    fn from(user: &User) -> Self {
+        AccountSnapshot {
+            client: user.id,
+            available: user.account.avalible(),
+            held: user.account.held,
+            total: user.account.total,
+            locked: user.frozen(),
+            freeze_reason: user.freeze_reason,
+            accepted_count: user.accepted_count,
+            rejected_count: user.rejected_count,
+            max_held: user.max_held,
+            frozen_at: user.frozen_at,
+            currency: None,
+        }
+    }
+}
+
+impl AccountSnapshot {
+    /// Snapshot of client balance in `currency`
+    fn with_balance(user: &User, currency: Option<Currency>, account: &Account) -> Self {
+        AccountSnapshot {
+            available: account.avalible(),
+            held: account.held,
+            total: account.total,
+            currency,
+            ..AccountSnapshot::from(user)
+        }
+    }
+
+    /// Snapshots of client balances in all currencies.
+    /// Empty default balance is skipped when there are others.
+    fn all_of(user: &User) -> impl Iterator<Item = AccountSnapshot> + '_ {
+        let only_others = !user.balances.is_empty() && user.account == Account::default();
+        user.all_balances()
+            .skip(only_others as usize)
+            .map(move |(currency, account)| Self::with_balance(user, currency, account))
+    }
+}
+
+/// Balances aggregated over all accounts in the default currency
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct EngineTotals {
+    pub total_available: Amount,
+    pub total_held: Amount,
+    pub total: Amount,
+    pub frozen_accounts: usize,
+}
+
+/// Counters of processed input rows
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ProcessStats {
+    pub rows: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+    /// Rows which couldn't be read or parsed into request
+    pub parse_errors: u64,
+    /// Rows skipped, as reading input failed while they were read
+    pub skipped: u64,
+}
+
+impl ProcessStats {
+    /// Adds rows of single input, classified by their warnings
+    fn record(&mut self, rows: u64, warnings: &[ProcessingWarning]) {
+        let rejected = warnings
+            .iter()
+            .filter(|w| matches!(w.kind, WarningKind::Rejected(_)))
+            .count() as u64;
+        let skipped = warnings
+            .iter()
+            .filter(|w| matches!(w.kind, WarningKind::Skipped(_)))
+            .count() as u64;
+        let parse_errors = warnings.len() as u64 - rejected - skipped;
+
+        self.rows += rows;
+        self.accepted += rows - rejected - parse_errors - skipped;
+        self.rejected += rejected;
+        self.parse_errors += parse_errors;
+        self.skipped += skipped;
+    }
+
+    /// Adds counters of other inputs
+    fn add(&mut self, other: &ProcessStats) {
+        self.rows += other.rows;
+        self.accepted += other.accepted;
+        self.rejected += other.rejected;
+        self.parse_errors += other.parse_errors;
+        self.skipped += other.skipped;
+    }
+}
+
+/// Change of client balances caused by successfully applied transaction
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BalanceEvent {
+    pub client: u16,
+    pub tx: u32,
+    /// Currency of changed balance, `None` for the default one
+    pub currency: Option<Currency>,
+    pub delta_total: Amount,
+    pub delta_held: Amount,
+}
+
+/// Record of successfully applied transaction
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    /// Position of the transaction among all processed requests
+    pub sequence: u64,
+    pub client: u16,
+    pub tx: u32,
+    pub tx_type: TransactionType,
+    pub amount: Option<Amount>,
+    /// Currency of changed balance, `None` for the default one
+    pub currency: Option<Currency>,
+    /// Client available funds in `currency` after the transaction
+    pub available: Amount,
+    /// Client held funds in `currency` after the transaction
+    pub held: Amount,
+}
+
+#[derive(Default, Serialize, Deserialize)]
 pub struct Engine {
-    pub users: HashMap<u16, User>,
+    /// Client accounts
+    ledger: Ledger,
+    /// Content hashes of processed input files
+    #[serde(default)]
+    processed_files: HashSet<u64>,
+    /// Refuse to process file with the same content twice
+    #[serde(skip)]
+    pub skip_processed_files: bool,
+    /// Log of applied transactions, collected when set to `Some`
+    #[serde(skip)]
+    pub audit: Option<Vec<AuditEntry>>,
+    /// Print processing warnings to stderr
+    #[serde(skip)]
+    pub print_warnings: bool,
+    #[serde(skip)]
+    pub config: Config,
+    #[serde(skip)]
+    pub input: InputFormat,
+    #[serde(skip)]
+    pub output: OutputFormat,
+    /// Counters accumulated over all processed inputs
+    #[serde(skip)]
+    pub stats: ProcessStats,
+    /// Input reading stops once set, e.g. on interrupt.
+    /// Requests read so far stay applied.
+    #[serde(skip)]
+    pub stop: Option<Arc<AtomicBool>>,
+    /// Called with client id whenever chargeback locks an account which wasn't locked
+    #[serde(skip)]
+    pub on_freeze: Option<Box<dyn FnMut(u16) + Send>>,
+    /// Receives balance change of every applied transaction,
+    /// except change which doesn't fit an amount
+    #[serde(skip)]
+    pub event_sink: Option<Box<dyn FnMut(BalanceEvent) + Send>>,
+    /// Verify conservation of client funds after every applied transaction,
+    /// panicking on first violation. Has effect only in debug builds
+    /// and needs full history, see `verify_conservation`.
+    #[serde(skip)]
+    pub check_invariants_each_row: bool,
 }
 
 impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates engine with room for `clients` accounts, avoiding rehashing while they are added
+    pub fn with_capacity(clients: usize) -> Self {
+        Engine {
+            ledger: Ledger::with_capacity(clients),
+            ..Default::default()
+        }
+    }
+
+    /// Transaction logic without input and output handling
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// Returns client account, if it exists
+    pub fn user(&self, client: u16) -> Option<&User> {
+        self.ledger.user(client)
+    }
+
+    /// Iterates over all accounts in unspecified order
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.ledger.users()
+    }
+
+    /// Returns client owning deposit or withdrawal with this id
+    pub fn client_of_tx(&self, tx: u32) -> Option<u16> {
+        self.ledger.client_of_tx(tx)
+    }
+
+    /// Removes all accounts and audit entries, keeping allocated capacity for
+    /// the next batch. Configuration, stats and processed file hashes are kept.
+    pub fn clear(&mut self) {
+        self.ledger.clear();
+        if let Some(audit) = &mut self.audit {
+            audit.clear();
+        }
+    }
+
+    /// Sets how far below zero client available funds may go on withdrawal
+    pub fn set_overdraft_limit(&mut self, client: u16, limit: Amount) -> Result<(), TxError> {
+        if limit < Amount::ZERO {
+            return Err(TxError::InvalidAmount);
+        }
+
+        self.ledger.user_mut(client).overdraft_limit = limit;
+        Ok(())
+    }
+
     /// Process single transaction
-    pub fn process_tx(&mut self, tx: TransactionRequset) -> Result<(), String> {
-        let user = match self.users.get_mut(&tx.client) {
-            Some(v) => v,
-            None => {
-                self.users.insert(
-                    tx.client,
-                    User {
-                        id: tx.client,
-                        account: Account {
-                            total: 0.0,
-                            held: 0.0,
-                        },
-                        tx_history: HashMap::new(),
-                        frozen: false,
-                    },
+    pub fn process_tx(&mut self, tx: &TransactionRequset) -> Result<(), TxError> {
+        let (tx_id, client, tx_type, amount) = (tx.tx, tx.client, tx.r#type, tx.amount);
+        let currency = self.currency_of(tx);
+        let was_frozen = self.ledger.user(client).is_some_and(User::frozen);
+        let before = match (&self.event_sink, self.ledger.user(client)) {
+            (Some(_), Some(user)) => user.balance(currency).cloned(),
+            _ => None,
+        };
+
+        self.ledger.apply(tx, &self.config)?;
+
+        #[cfg(debug_assertions)]
+        if self.check_invariants_each_row {
+            if let Err(e) = Self::conservation_of(self.ledger.user(client).into_iter()) {
+                panic!(
+                    "Transaction {} of client {} broke conservation: {}",
+                    tx_id, client, e
                 );
-                self.users.get_mut(&tx.client).unwrap()
             }
-        };
+        }
+
+        if let Some(sink) = &mut self.event_sink {
+            let before = before.unwrap_or_default();
+            let after = self
+                .ledger
+                .user(client)
+                .and_then(|u| u.balance(currency))
+                .cloned()
+                .unwrap_or_default();
+            // Change too large to be an amount can't be reported
+            if let (Some(delta_total), Some(delta_held)) = (
+                after.total.checked_sub(before.total),
+                after.held.checked_sub(before.held),
+            ) {
+                sink(BalanceEvent {
+                    client,
+                    tx: tx_id,
+                    currency,
+                    delta_total,
+                    delta_held,
+                });
+            }
+        }
+
+        let frozen = self.ledger.user(client).is_some_and(User::frozen);
+        if tx_type == TransactionType::Chargeback && !was_frozen && frozen {
+            if let Some(on_freeze) = &mut self.on_freeze {
+                on_freeze(client);
+            }
+        }
+
+        if let Some(audit) = &mut self.audit {
+            let account = self
+                .ledger
+                .user(client)
+                .and_then(|u| u.balance(currency))
+                .cloned()
+                .unwrap_or_default();
+            audit.push(AuditEntry {
+                sequence: self.ledger.sequence(),
+                client,
+                tx: tx_id,
+                tx_type,
+                amount,
+                currency,
+                available: account.avalible(),
+                held: account.held,
+            });
+        }
 
-        user.process_tx(tx)
+        Ok(())
     }
 
-    /// Processes file with pending transactions
-    pub fn process_data(&mut self, path: &std::path::Path) -> Result<(), io::Error> {
+    /// Sets receiver of balance changes, e.g. to update views incrementally
+    pub fn set_event_sink<F: FnMut(BalanceEvent) + Send + 'static>(&mut self, sink: F) {
+        self.event_sink = Some(Box::new(sink));
+    }
+
+    /// Combines state of engine which processed another part of input.
+    /// Its stats and processed files are added. Audit entries of `other` follow
+    /// these, numbered after requests of this engine. Fails without changes
+    /// if both used the same transaction id or any balance would overflow.
+    pub fn merge(&mut self, other: Engine) -> Result<(), TxError> {
+        let sequence = self.ledger.sequence();
+        self.ledger.merge(other.ledger)?;
+        self.stats.add(&other.stats);
+        self.processed_files.extend(other.processed_files);
+        if let (Some(audit), Some(other_audit)) = (&mut self.audit, other.audit) {
+            audit.extend(other_audit.into_iter().map(|entry| AuditEntry {
+                sequence: sequence + entry.sequence,
+                ..entry
+            }));
+        }
+        Ok(())
+    }
+
+    /// Currency of balance affected by request, see `User::currency_of`
+    fn currency_of(&self, tx: &TransactionRequset) -> Option<Currency> {
+        self.ledger
+            .user(tx.client)
+            .map_or(tx.currency, |user| user.currency_of(tx))
+    }
+
+    /// Processes single transaction and returns updated state of client balance it changed
+    pub fn submit(&mut self, tx: &TransactionRequset) -> Result<AccountSnapshot, TxError> {
+        let currency = self.currency_of(tx);
+        self.process_tx(tx)?;
+        Ok(self
+            .currency_snapshot(tx.client, currency)
+            .expect("Balance exists after applied request"))
+    }
+
+    /// Returns current state of client account in the default currency, if it exists
+    pub fn account_snapshot(&self, client: u16) -> Option<AccountSnapshot> {
+        self.ledger.user(client).map(AccountSnapshot::from)
+    }
+
+    /// Returns current state of client balance in `currency`, if it exists
+    pub fn currency_snapshot(
+        &self,
+        client: u16,
+        currency: Option<Currency>,
+    ) -> Option<AccountSnapshot> {
+        let user = self.ledger.user(client)?;
+        Some(AccountSnapshot::with_balance(
+            user,
+            currency,
+            user.balance(currency)?,
+        ))
+    }
+
+    /// Iterates over snapshots of all accounts in unspecified order,
+    /// one for each currency used by client
+    pub fn accounts(&self) -> impl Iterator<Item = AccountSnapshot> + '_ {
+        self.ledger.users().flat_map(AccountSnapshot::all_of)
+    }
+
+    /// Lists `(client, tx, held amount)` of all disputed transactions,
+    /// sorted by client and transaction id. Held amount of withdrawal is negative.
+    pub fn open_disputes(&self) -> Vec<(u16, u32, Amount)> {
+        let mut disputes: Vec<_> = self
+            .ledger
+            .users()
+            .flat_map(|user| {
+                user.tx_history
+                    .iter()
+                    .filter(|(_, t)| t.state == TransactionState::Disputed)
+                    .map(move |(&tx, t)| (user.id, tx, t.disputed_effect()))
+            })
+            .collect();
+        disputes.sort_unstable_by_key(|&(client, tx, _)| (client, tx));
+        disputes
+    }
+
+    /// Sums balances of all accounts in the default currency, fails if a sum is out of range.
+    /// Balances in other currencies are left out, as they can't be added together.
+    pub fn totals(&self) -> Result<EngineTotals, TxError> {
+        self.ledger
+            .users()
+            .try_fold(EngineTotals::default(), |totals, user| {
+                Some(EngineTotals {
+                    total_available: totals
+                        .total_available
+                        .checked_add(user.account.avalible())?,
+                    total_held: totals.total_held.checked_add(user.account.held)?,
+                    total: totals.total.checked_add(user.account.total)?,
+                    frozen_accounts: totals.frozen_accounts + user.frozen() as usize,
+                })
+            })
+            .ok_or(TxError::Overflow)
+    }
+
+    /// Checks that history of all clients adds up to their totals,
+    /// accounting for charged back funds. Fails if history was evicted.
+    pub fn verify_conservation(&self) -> Result<(), String> {
+        Self::conservation_of(self.ledger.users())
+    }
+
+    /// Sums are kept in raw ten-thousandths as `i128`, so they can't overflow
+    fn conservation_of<'a>(users: impl Iterator<Item = &'a User>) -> Result<(), String> {
+        let (mut moved, mut totals, mut charged_back) = (0_i128, 0_i128, 0_i128);
+        for user in users {
+            for (_, account) in user.all_balances() {
+                totals += account.total.raw() as i128;
+            }
+            for t in user.tx_history.values() {
+                moved += t.balance_effect().raw() as i128;
+                if t.state == TransactionState::Chargedback {
+                    charged_back += t.disputed_effect().raw() as i128;
+                }
+            }
+        }
+
+        if moved == totals + charged_back {
+            Ok(())
+        } else {
+            let show = |raw: i128| match i64::try_from(raw) {
+                Ok(raw) => Amount::from_raw(raw).to_string(),
+                Err(_) => format!("{} ten-thousandths", raw),
+            };
+            Err(format!(
+                "Transactions moved {}, but totals are {} and {} was charged back",
+                show(moved),
+                show(totals),
+                show(charged_back)
+            ))
+        }
+    }
+
+    /// Opens input file.
+    /// Files with `.gz` extension are decompressed on the fly.
+    fn open_input(path: &Path) -> Result<Box<dyn io::Read + Send>, io::Error> {
+        let file = std::fs::File::open(path)?;
+        if path.extension().is_some_and(|ext| ext == "gz") {
+            Ok(Box::new(flate2::read::GzDecoder::new(file)))
+        } else {
+            Ok(Box::new(file))
+        }
+    }
+
+    /// Parses CSV stream and passes each valid request with its line number to `handle`.
+    /// Parse errors and rejected requests are collected into `warnings`.
+    /// Returns number of rows read.
+    fn read_requests<R, F>(
+        reader: R,
+        input: &InputFormat,
+        stop: Option<&AtomicBool>,
+        warnings: &mut Vec<ProcessingWarning>,
+        mut handle: F,
+    ) -> Result<u64, io::Error>
+    where
+        R: io::Read,
+        F: FnMut(u64, TransactionRequset) -> Result<(), TxError>,
+    {
+        input
+            .validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        // Leading UTF-8 BOM is stripped by reader.
+        // Field count is checked below, so whitespace-only lines can be skipped.
         let mut rdr = csv::ReaderBuilder::new()
             .trim(csv::Trim::All)
-            .from_path(path)?;
-        for record in rdr.records() {
-            match record.unwrap().deserialize::<TransactionRequset>(None) {
-                Err(e) => {
-                    eprintln!("Request parse error: {:?}", e);
+            .flexible(true)
+            .has_headers(input.has_headers)
+            .delimiter(input.delimiter)
+            .from_reader(RetryReader {
+                inner: reader,
+                retry: !input.stop_on_io_error,
+                failed: None,
+                gave_up: false,
+            });
+
+        // Optional columns are matched by name when header is present,
+        // otherwise they are expected in `OPTIONAL_COLUMNS` order
+        let (min_fields, mut max_fields) = (HEADER.len(), HEADER.len() + OPTIONAL_COLUMNS.len());
+        let mut named = None;
+        if input.has_headers {
+            let headers = rdr.headers()?.clone();
+            // Header can't be skipped like a record
+            if let Some(e) = rdr.get_mut().failed.take() {
+                return Err(e);
+            }
+            let known = headers.iter().take(HEADER.len()).eq(HEADER.iter().copied());
+            let extra: Vec<&str> = headers.iter().skip(HEADER.len()).collect();
+            let optional = extra.iter().enumerate().all(|(i, column)| {
+                OPTIONAL_COLUMNS.contains(column) && !extra[..i].contains(column)
+            });
+            // Empty input has nothing to validate
+            if !(headers.is_empty() || known && optional) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Invalid CSV header: expected \"{}\", found \"{}\"",
+                        HEADER.join(","),
+                        headers.iter().collect::<Vec<_>>().join(",")
+                    ),
+                ));
+            }
+            if !headers.is_empty() {
+                max_fields = headers.len();
+                named = Some(headers);
+            }
+        }
+
+        let mut rows = 0;
+        let mut record = csv::StringRecord::new();
+        loop {
+            if stop.is_some_and(|s| s.load(Ordering::Relaxed)) {
+                break;
+            }
+            let read = rdr.read_record(&mut record);
+            let line = record.position().map_or(0, |p| p.line());
+            let retry = rdr.get_mut();
+            if let Some(e) = retry.failed.take() {
+                let error = match retry.gave_up {
+                    true => format!("{}, rest of input not read", e),
+                    false => e.to_string(),
+                };
+                // Record read across failed read may be incomplete
+                rows += 1;
+                warnings.push(ProcessingWarning {
+                    line,
+                    kind: WarningKind::Skipped(error),
+                });
+                match read {
+                    Ok(false) => break,
+                    _ => continue,
                 }
-                Ok(request) => {
-                    if let Err(e) = self.process_tx(request) {
-                        eprintln!("{}", e);
-                    }
+            }
+            let result = match read {
+                Ok(false) => break,
+                Ok(true) if record.iter().all(str::is_empty) => continue,
+                Ok(true) if !(min_fields..=max_fields).contains(&record.len()) => {
+                    Err(WarningKind::Read(if min_fields == max_fields {
+                        format!(
+                            "found record with {} fields, expected {}",
+                            record.len(),
+                            min_fields
+                        )
+                    } else {
+                        format!(
+                            "found record with {} fields, expected {} or {}",
+                            record.len(),
+                            min_fields,
+                            max_fields
+                        )
+                    }))
                 }
+                // Only without retries, see `InputFormat::stop_on_io_error`
+                Err(e) if e.is_io_error() => return Err(e.into()),
+                // Reader skips malformed record
+                Err(e) => Err(WarningKind::Read(e.to_string())),
+                Ok(true) => match record
+                    .deserialize::<CsvRecord>(named.as_ref())
+                    .map_err(|e| WarningKind::Parse(e.to_string()))
+                    .and_then(|r| r.into_request(input))
+                {
+                    Err(WarningKind::UnknownType(t)) if input.strict_types => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Line {}: Unknown transaction type \"{}\"", line, t),
+                        ));
+                    }
+                    Err(kind) => Err(kind),
+                    Ok(request) if !input.accepts_client(request.client) => continue,
+                    Ok(request) => handle(line, request).map_err(WarningKind::Rejected),
+                },
+            };
+            rows += 1;
+            if let Err(kind) = result {
+                warnings.push(ProcessingWarning { line, kind });
             }
         }
 
-        Ok(())
+        Ok(rows)
     }
 
-    /// Prints all users data.
-    pub fn print_users(&self) {
-        println!("client,available,held,total,locked");
-        for user in self.users.values() {
-            println!(
-                "{}\t{:.4}\t{:.4}\t{:.4}\t{}",
-                user.id,
-                user.account.avalible(),
-                user.account.held,
-                user.account.total,
-                user.frozen
-            );
+    /// Prints warnings to stderr if enabled
+    fn report_warnings(&self, warnings: &[ProcessingWarning]) {
+        if self.print_warnings {
+            for warning in warnings {
+                eprintln!("{}", warning);
+            }
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use pretty_assertions::assert_eq;
-    use std::io::Write;
-    use tempfile::tempdir;
+    /// Processes file with pending transactions
+    pub fn process_data(&mut self, path: &Path) -> Result<Vec<ProcessingWarning>, io::Error> {
+        let hash = self.unprocessed_hash(path)?;
+        let reader = Self::open_input(path)?;
+        let warnings = self.process_reader(reader)?;
+        self.processed_files.extend(hash);
 
-    #[test]
-    fn test_process_tx() {
-        let mut engine = Engine {
-            users: HashMap::new(),
+        Ok(warnings)
+    }
+
+    /// Hash of file to remember once it's processed, if `skip_processed_files` is set.
+    /// Fails if file with the same content was already processed.
+    fn unprocessed_hash(&self, path: &Path) -> Result<Option<u64>, io::Error> {
+        if !self.skip_processed_files {
+            return Ok(None);
+        }
+        let hash = Self::file_hash(path)?;
+        if self.processed_files.contains(&hash) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("File {} was already processed", path.display()),
+            ));
+        }
+        Ok(Some(hash))
+    }
+
+    /// Checks if request of this type refers to earlier transaction
+    fn references_tx(tx_type: TransactionType) -> bool {
+        matches!(
+            tx_type,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+        )
+    }
+
+    /// FNV-1a hash of file content, stable between runs
+    fn file_hash(path: &Path) -> Result<u64, io::Error> {
+        let mut reader = io::BufReader::new(std::fs::File::open(path)?);
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        loop {
+            let buf = io::BufRead::fill_buf(&mut reader)?;
+            if buf.is_empty() {
+                return Ok(hash);
+            }
+            for &byte in buf {
+                hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+            let len = buf.len();
+            io::BufRead::consume(&mut reader, len);
+        }
+    }
+
+    /// Reports which requests from file would be rejected, with their line numbers.
+    /// Requests are applied to a copy of the engine, so its state is not changed.
+    pub fn validate_data(&self, path: &Path) -> Result<Vec<(usize, TxError)>, io::Error> {
+        let mut shadow = Engine {
+            ledger: self.ledger.clone(),
+            config: self.config.clone(),
+            input: self.input.clone(),
+            ..Default::default()
         };
+        let warnings = shadow.process_data(path)?;
 
-        let mut tx = TransactionRequset {
-            r#type: TransactionType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(10.0),
+        Ok(warnings
+            .into_iter()
+            .filter_map(|w| match w.kind {
+                WarningKind::Rejected(e) => Some((w.line as usize, e)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Processes files in order against the same state,
+    /// so later files may dispute transactions from earlier ones
+    pub fn process_files(&mut self, paths: &[PathBuf]) -> Result<(), io::Error> {
+        for path in paths {
+            self.process_data(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Processes in-memory CSV data, e.g. fixtures or fuzzer input
+    pub fn process_bytes(&mut self, data: &[u8]) -> Result<Vec<ProcessingWarning>, io::Error> {
+        self.process_reader(io::Cursor::new(data))
+    }
+
+    /// Processes CSV stream with pending transactions
+    pub fn process_reader<R: io::Read>(
+        &mut self,
+        reader: R,
+    ) -> Result<Vec<ProcessingWarning>, io::Error> {
+        let mut warnings = Vec::new();
+        let mut deferred = Vec::new();
+        let input = self.input.clone();
+        let stop = self.stop.clone();
+        let rows = Self::read_requests(
+            reader,
+            &input,
+            stop.as_deref(),
+            &mut warnings,
+            |line, request| {
+                if input.retry_unknown
+                    && Self::references_tx(request.r#type)
+                    && !self.ledger.knows_tx(request.tx)
+                {
+                    deferred.push((line, request));
+                    return Ok(());
+                }
+                self.process_tx(&request)
+            },
+        )?;
+
+        // Referenced transactions may have appeared later in input
+        if !deferred.is_empty() {
+            for (line, request) in deferred {
+                if let Err(e) = self.process_tx(&request) {
+                    warnings.push(ProcessingWarning {
+                        line,
+                        kind: WarningKind::Rejected(e),
+                    });
+                }
+            }
+            warnings.sort_by_key(|w| w.line);
+        }
+        self.stats.record(rows, &warnings);
+        self.report_warnings(&warnings);
+
+        Ok(warnings)
+    }
+
+    /// Processes file with pending transactions using `num_threads` workers.
+    /// Clients are sharded by `client % num_threads`, so requests of single
+    /// client are still processed in input order. Owners of transaction ids are
+    /// checked while reading. Request reusing id of another client waits until
+    /// shard of the earlier request decides if the id was taken, as `Ledger::apply` does.
+    /// Audit log, freeze callback and event sink get entries in input order
+    /// once all input is processed.
+    pub fn process_data_parallel(
+        &mut self,
+        path: &Path,
+        num_threads: usize,
+    ) -> Result<Vec<ProcessingWarning>, io::Error> {
+        let num_threads = num_threads.max(1);
+        let hash = self.unprocessed_hash(path)?;
+        let reader = Self::open_input(path)?;
+        let (outcome_sender, outcomes) = mpsc::channel();
+
+        let (senders, workers): (Vec<_>, Vec<_>) = self
+            .ledger
+            .split(num_threads)
+            .into_iter()
+            .map(|ledger| {
+                // Callbacks run on this thread, so shards only collect their arguments
+                let (freeze_sender, freezes) = mpsc::channel();
+                let (event_sender, events) = mpsc::channel();
+                let mut shard = Engine {
+                    ledger,
+                    config: self.config.clone(),
+                    audit: self.audit.as_ref().map(|_| Vec::new()),
+                    check_invariants_each_row: self.check_invariants_each_row,
+                    on_freeze: match self.on_freeze {
+                        Some(_) => Some(Box::new(move |client| {
+                            let _ = freeze_sender.send(client);
+                        })),
+                        None => None,
+                    },
+                    event_sink: match self.event_sink {
+                        Some(_) => Some(Box::new(move |event| {
+                            let _ = event_sender.send(event);
+                        })),
+                        None => None,
+                    },
+                    ..Default::default()
+                };
+
+                let (sender, receiver) = mpsc::sync_channel(SHARD_QUEUE_SIZE);
+                let outcome_sender = outcome_sender.clone();
+                let worker = thread::spawn(move || {
+                    let mut output = ShardOutput::default();
+                    for (line, sequence, request) in receiver {
+                        // Numbering of whole input, not only of this shard
+                        shard.ledger.sequence = sequence - 1;
+                        let result = shard.process_tx(&request);
+                        if Ledger::creates_tx(request.r#type) {
+                            let recorded = shard.ledger.client_of_tx(request.tx);
+                            let outcome = (request.tx, sequence, recorded == Some(request.client));
+                            let _ = outcome_sender.send(outcome);
+                        }
+                        if let Err(e) = result {
+                            output.warnings.push(ProcessingWarning {
+                                line,
+                                kind: WarningKind::Rejected(e),
+                            });
+                        }
+                        output
+                            .freezes
+                            .extend(freezes.try_iter().map(|client| (sequence, client)));
+                        output
+                            .events
+                            .extend(events.try_iter().map(|event| (sequence, event)));
+                    }
+                    (shard, output)
+                });
+                (sender, worker)
+            })
+            .unzip();
+        drop(outcome_sender);
+
+        let mut dispatcher = ShardDispatcher {
+            senders,
+            workers,
+            allowed_types: self.config.allowed_types.clone(),
+            // Shards know only owners of their clients' transactions
+            owners: self.ledger.tx_owners.clone(),
+            pending: IdMap::default(),
+            outcomes,
+            sequence: self.ledger.sequence(),
+            warnings: Vec::new(),
         };
+        let mut warnings = Vec::new();
+        let mut deferred = Vec::new();
+        let retry_unknown = self.input.retry_unknown;
+        let stop = self.stop.as_deref();
+        let result =
+            Self::read_requests(reader, &self.input, stop, &mut warnings, |line, request| {
+                if retry_unknown
+                    && Self::references_tx(request.r#type)
+                    && !dispatcher.knows_tx(request.tx)
+                {
+                    deferred.push((line, request));
+                } else {
+                    dispatcher.dispatch(line, request);
+                }
+                Ok(())
+            });
+        // Referenced transactions may have appeared later in input
+        if result.is_ok() {
+            for (line, request) in deferred {
+                dispatcher.dispatch(line, request);
+            }
+        }
+        let ShardDispatcher {
+            senders,
+            workers,
+            sequence,
+            warnings: claim_warnings,
+            ..
+        } = dispatcher;
+        drop(senders);
+        warnings.extend(claim_warnings);
 
-        assert!(engine.process_tx(tx.clone()).is_ok());
-        tx.tx = 2;
-        assert!(engine.process_tx(tx.clone()).is_ok());
-        tx.client = 2;
-        tx.tx = 3;
-        assert!(engine.process_tx(tx.clone()).is_ok());
-        assert_eq!(engine.users.len(), 2);
+        let (mut audit, mut freezes, mut events) = (Vec::new(), Vec::new(), Vec::new());
+        for worker in workers {
+            let (mut shard, output) = worker.join().expect("Shard worker panicked");
+            self.ledger.absorb(shard.ledger);
+            audit.extend(shard.audit.take().into_iter().flatten());
+            warnings.extend(output.warnings);
+            freezes.extend(output.freezes);
+            events.extend(output.events);
+        }
+        // Requests rejected while reading are numbered as well
+        self.ledger.sequence = self.ledger.sequence.max(sequence);
+        // Shards keep their own order, so stable sort restores input order
+        audit.sort_by_key(|entry| entry.sequence);
+        freezes.sort_by_key(|&(sequence, _)| sequence);
+        events.sort_by_key(|&(sequence, _)| sequence);
+        if let Some(entries) = &mut self.audit {
+            entries.extend(audit);
+        }
+        if let Some(on_freeze) = &mut self.on_freeze {
+            freezes
+                .into_iter()
+                .for_each(|(_, client)| on_freeze(client));
+        }
+        if let Some(sink) = &mut self.event_sink {
+            events.into_iter().for_each(|(_, event)| sink(event));
+        }
+        let rows = result?;
+        self.processed_files.extend(hash);
 
-        tx.client = 1;
-        tx.tx = 4;
-        tx.r#type = TransactionType::Withdrawal;
-        tx.amount = Some(5.0);
-        assert!(engine.process_tx(tx.clone()).is_ok());
-        assert_eq!(engine.users.get(&1).unwrap().account.total, 15.0);
-        assert_eq!(engine.users.get(&1).unwrap().account.avalible(), 15.0);
+        warnings.sort_by_key(|w| w.line);
+        self.stats.record(rows, &warnings);
+        self.report_warnings(&warnings);
 
-        // Not in despute
-        tx.tx = 1;
-        tx.r#type = TransactionType::Resolve;
-        assert!(engine.process_tx(tx.clone()).is_err());
+        Ok(warnings)
+    }
 
-        tx.r#type = TransactionType::Dispute;
-        assert!(engine.process_tx(tx.clone()).is_ok());
-        assert_eq!(engine.users.get(&1).unwrap().account.total, 15.0);
-        assert_eq!(engine.users.get(&1).unwrap().account.avalible(), 5.0);
+    /// Saves accounts and transaction history, so processing can be resumed later
+    pub fn save_state<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        serde_json::to_writer(w, self)?;
+        Ok(())
+    }
 
-        tx.r#type = TransactionType::Resolve;
-        assert!(engine.process_tx(tx.clone()).is_ok());
-        assert_eq!(engine.users.get(&1).unwrap().account.total, 15.0);
-        assert_eq!(engine.users.get(&1).unwrap().account.avalible(), 15.0);
+    /// Restores engine saved with `save_state`. Configuration is reset to defaults.
+    pub fn load_state<R: io::Read>(r: &mut R) -> io::Result<Engine> {
+        Ok(serde_json::from_reader(r)?)
+    }
 
-        tx.r#type = TransactionType::Dispute;
-        assert!(engine.process_tx(tx.clone()).is_ok());
-        assert_eq!(engine.users.get(&1).unwrap().account.total, 15.0);
-        assert_eq!(engine.users.get(&1).unwrap().account.avalible(), 5.0);
+    /// Writes all users data in bincode format, read back with `Report::read_bincode`
+    #[cfg(feature = "bincode")]
+    pub fn write_report_bincode<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.report().write_bincode(w)
+    }
 
-        tx.r#type = TransactionType::Chargeback;
-        assert!(engine.process_tx(tx.clone()).is_ok());
-        assert_eq!(engine.users.get(&1).unwrap().account.total, 5.0);
-        assert_eq!(engine.users.get(&1).unwrap().account.avalible(), 5.0);
+    /// Collects state of all accounts sorted by client id
+    pub fn report(&self) -> Report {
+        let mut accounts: Vec<AccountSnapshot> = self.accounts().collect();
+        accounts.sort_by_key(|s| (s.client, s.currency));
+        Report {
+            accounts,
+            precision: self.output.precision,
+            trim_trailing_zeros: self.output.trim_trailing_zeros,
+        }
+    }
 
-        // User locked
-        tx.r#type = TransactionType::Deposit;
-        assert!(engine.process_tx(tx.clone()).is_err());
+    /// Report with single client account, if it exists
+    pub fn client_report(&self, client: u16) -> Option<Report> {
+        Some(Report {
+            accounts: AccountSnapshot::all_of(self.ledger.user(client)?).collect(),
+            precision: self.output.precision,
+            trim_trailing_zeros: self.output.trim_trailing_zeros,
+        })
     }
 
-    #[test]
-    fn test_process_data() {
-        let dir = tempdir().unwrap();
-        let path = dir.path().join("input.csv");
-        let mut file = std::fs::File::create(&path).unwrap();
+    /// Writes all users data as CSV report.
+    pub fn write_report<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.report().write_csv(w)
+    }
 
-        let data = r#"type,client,tx,amount
-        deposit,    1,      1,  1.0
-        deposit,    2,      2,  2.0
-        deposit,    1,      3,  2.0
-        withdrawal, 1,      4,  1.5
-        withdrawal, 2,      5,  3.0"#;
-        write!(file, "{}", data).unwrap();
+    /// Writes CSV report like `write_report`, but holds at most `chunk`
+    /// account snapshots at a time and flushes after each chunk
+    pub fn stream_report<W: io::Write>(&self, w: &mut W, chunk: usize) -> io::Result<()> {
+        let mut clients: Vec<u16> = self.ledger.users.keys().copied().collect();
+        clients.sort_unstable();
 
-        let mut engine = Engine {
-            users: HashMap::new(),
+        let mut report = Report {
+            accounts: Vec::with_capacity(chunk),
+            precision: self.output.precision,
+            trim_trailing_zeros: self.output.trim_trailing_zeros,
         };
+        let with_currency = self.users().any(|u| !u.balances.is_empty());
+        let mut wtr = csv::Writer::from_writer(w);
+        Report::write_csv_header(&mut wtr, with_currency)?;
+        for ids in clients.chunks(chunk.max(1)) {
+            report.accounts.clear();
+            report.accounts.extend(
+                ids.iter()
+                    .flat_map(|id| AccountSnapshot::all_of(&self.ledger.users[id])),
+            );
+            report.write_csv_rows(&mut wtr, with_currency)?;
+            wtr.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes stored transactions of all clients as CSV, sorted by client and transaction id.
+    /// Currency column is added when any transaction has one.
+    /// Transactions evicted from history are not included.
+    pub fn export_transactions<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut transactions: Vec<(u16, u32, &Transatcion)> = self
+            .users()
+            .flat_map(|u| u.tx_history.iter().map(move |(&tx, t)| (u.id, tx, t)))
+            .collect();
+        transactions.sort_unstable_by_key(|&(client, tx, _)| (client, tx));
+        let with_currency = transactions.iter().any(|(_, _, t)| t.currency.is_some());
+
+        let (precision, trim) = (self.output.precision, self.output.trim_trailing_zeros);
+        let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(w);
+        let mut header = EXPORT_HEADER.to_vec();
+        if with_currency {
+            header.push(CURRENCY_COLUMN);
+        }
+        wtr.write_record(&header)?;
+        for (client, tx, t) in transactions {
+            let amount = t.amount.display(precision).trimmed(trim).to_string();
+            let fee = t.fee.display(precision).trimmed(trim).to_string();
+            if with_currency {
+                let currency = t.currency.map_or_else(String::new, |c| c.to_string());
+                wtr.serialize((client, tx, t.tx_type, amount, fee, t.state, currency))?;
+            } else {
+                wtr.serialize((client, tx, t.tx_type, amount, fee, t.state))?;
+            }
+        }
+
+        wtr.flush()
+    }
+
+    /// Writes all users data as JSON array.
+    pub fn write_report_json<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.report().write_json(w)
+    }
+
+    /// Prints all users data.
+    pub fn print_users(&self) -> io::Result<()> {
+        self.write_report(&mut io::stdout().lock())
+    }
+
+    /// Prints all users data as JSON.
+    pub fn print_users_json(&self) -> io::Result<()> {
+        self.write_report_json(&mut io::stdout().lock())
+    }
+}
+
+/// State of all accounts, rendered independently of the engine
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub accounts: Vec<AccountSnapshot>,
+    /// Decimal places of amounts in CSV output
+    pub precision: usize,
+    /// Omit trailing zeros of amounts in CSV output
+    pub trim_trailing_zeros: bool,
+}
+
+impl Report {
+    /// Writes accounts as CSV, amounts rounded to `precision`.
+    /// Currency column is added when any account has one.
+    pub fn write_csv<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let with_currency = self.accounts.iter().any(|s| s.currency.is_some());
+        let mut wtr = csv::Writer::from_writer(w);
+        Self::write_csv_header(&mut wtr, with_currency)?;
+        self.write_csv_rows(&mut wtr, with_currency)?;
+        wtr.flush()
+    }
+
+    fn write_csv_header<W: io::Write>(
+        wtr: &mut csv::Writer<W>,
+        with_currency: bool,
+    ) -> io::Result<()> {
+        for column in REPORT_HEADER.iter() {
+            wtr.write_field(column)?;
+        }
+        if with_currency {
+            wtr.write_field(CURRENCY_COLUMN)?;
+        }
+        wtr.write_record(None::<&[u8]>)?;
+        Ok(())
+    }
+
+    /// Writes accounts as CSV rows without header
+    fn write_csv_rows<W: io::Write>(
+        &self,
+        wtr: &mut csv::Writer<W>,
+        with_currency: bool,
+    ) -> io::Result<()> {
+        let (precision, trim) = (self.precision, self.trim_trailing_zeros);
+        for snapshot in &self.accounts {
+            let mut row = vec![
+                snapshot.client.to_string(),
+                snapshot
+                    .available
+                    .display(precision)
+                    .trimmed(trim)
+                    .to_string(),
+                snapshot.held.display(precision).trimmed(trim).to_string(),
+                snapshot.total.display(precision).trimmed(trim).to_string(),
+                snapshot.locked.to_string(),
+            ];
+            if with_currency {
+                row.push(
+                    snapshot
+                        .currency
+                        .map_or_else(String::new, |c| c.to_string()),
+                );
+            }
+            wtr.write_record(&row)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes accounts as JSON array
+    pub fn write_json<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        serde_json::to_writer(&mut *w, &self.accounts)?;
+        writeln!(w)
+    }
+
+    /// Writes accounts in bincode format
+    #[cfg(feature = "bincode")]
+    pub fn write_bincode<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        bincode::serialize_into(w, &self.accounts).map_err(|e| bincode_error(*e))
+    }
+
+    /// Reads accounts written with `write_bincode`
+    #[cfg(feature = "bincode")]
+    pub fn read_bincode<R: io::Read>(r: &mut R) -> io::Result<Vec<AccountSnapshot>> {
+        bincode::deserialize_from(r).map_err(|e| bincode_error(*e))
+    }
+
+    pub fn to_csv_string(&self) -> String {
+        let mut out = Vec::new();
+        self.write_csv(&mut out).expect("Writing to Vec can't fail");
+        String::from_utf8(out).expect("Report is valid UTF-8")
+    }
+
+    pub fn to_json_string(&self) -> String {
+        let mut out = Vec::new();
+        self.write_json(&mut out)
+            .expect("Writing to Vec can't fail");
+        String::from_utf8(out).expect("Report is valid UTF-8")
+    }
+}
+
+/// Converts bincode error, keeping I/O errors as they are
+#[cfg(feature = "bincode")]
+fn bincode_error(e: bincode::ErrorKind) -> io::Error {
+    match e {
+        bincode::ErrorKind::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+/// Creates `Engine` with chosen options, others are left default
+#[derive(Default)]
+pub struct EngineBuilder {
+    engine: Engine,
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.engine.config = config;
+        self
+    }
+
+    pub fn with_max_amount(mut self, max_amount: Amount) -> Self {
+        self.engine.config.max_amount = max_amount;
+        self
+    }
+
+    pub fn with_min_amount(mut self, min_amount: Amount) -> Self {
+        self.engine.config.min_amount = min_amount;
+        self
+    }
+
+    pub fn with_input(mut self, input: InputFormat) -> Self {
+        self.engine.input = input;
+        self
+    }
+
+    pub fn with_rounding(mut self, rounding: RoundingMode) -> Self {
+        self.engine.input.rounding = rounding;
+        self
+    }
+
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        self.engine.input.delimiter = delimiter;
+        self
+    }
+
+    pub fn with_precision(mut self, precision: usize) -> Self {
+        self.engine.output.precision = precision;
+        self
+    }
+
+    pub fn with_trim_trailing_zeros(mut self, trim: bool) -> Self {
+        self.engine.output.trim_trailing_zeros = trim;
+        self
+    }
+
+    /// Sets minor unit of currency, used both for parsed and reported amounts.
+    /// Fails when `decimals` exceeds `AMOUNT_DECIMALS`, as such amounts can't be stored.
+    pub fn with_decimals(mut self, decimals: u32) -> Result<Self, String> {
+        Amount::check_decimals(decimals)?;
+        self.engine.input.decimals = decimals;
+        self.engine.output.precision = decimals as usize;
+        Ok(self)
+    }
+
+    /// Collects log of applied transactions
+    pub fn with_audit(mut self) -> Self {
+        self.engine.audit = Some(Vec::new());
+        self
+    }
+
+    pub fn with_print_warnings(mut self, print_warnings: bool) -> Self {
+        self.engine.print_warnings = print_warnings;
+        self
+    }
+
+    pub fn with_on_freeze<F: FnMut(u16) + Send + 'static>(mut self, on_freeze: F) -> Self {
+        self.engine.on_freeze = Some(Box::new(on_freeze));
+        self
+    }
+
+    pub fn build(self) -> Engine {
+        self.engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tempfile::tempdir;
+
+    fn amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_process_tx() {
+        let mut engine = Engine::new();
+
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(amount("10.0")),
+            timestamp: None,
+            currency: None,
+        };
+
+        assert!(engine.process_tx(&tx).is_ok());
+        tx.tx = 2;
+        assert!(engine.process_tx(&tx).is_ok());
+        tx.client = 2;
+        tx.tx = 3;
+        assert!(engine.process_tx(&tx).is_ok());
+        assert_eq!(engine.ledger.users.len(), 2);
+
+        tx.client = 1;
+        tx.tx = 4;
+        tx.r#type = TransactionType::Withdrawal;
+        tx.amount = Some(amount("5.0"));
+        assert!(engine.process_tx(&tx).is_ok());
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.total,
+            amount("15.0")
+        );
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.avalible(),
+            amount("15.0")
+        );
+
+        // Not in despute
+        tx.tx = 1;
+        tx.amount = None;
+        tx.r#type = TransactionType::Resolve;
+        assert_eq!(engine.process_tx(&tx).unwrap_err(), TxError::NotDisputed(1));
+
+        tx.r#type = TransactionType::Dispute;
+        assert!(engine.process_tx(&tx).is_ok());
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.total,
+            amount("15.0")
+        );
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.avalible(),
+            amount("5.0")
+        );
+
+        tx.r#type = TransactionType::Resolve;
+        assert!(engine.process_tx(&tx).is_ok());
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.total,
+            amount("15.0")
+        );
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.avalible(),
+            amount("15.0")
+        );
+
+        tx.r#type = TransactionType::Dispute;
+        assert!(engine.process_tx(&tx).is_ok());
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.total,
+            amount("15.0")
+        );
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.avalible(),
+            amount("5.0")
+        );
+
+        tx.r#type = TransactionType::Chargeback;
+        assert!(engine.process_tx(&tx).is_ok());
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.total,
+            amount("5.0")
+        );
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.avalible(),
+            amount("5.0")
+        );
+
+        // User locked
+        tx.r#type = TransactionType::Deposit;
+        tx.tx = 5;
+        assert_eq!(engine.process_tx(&tx).unwrap_err(), TxError::AccountFrozen);
+    }
+
+    #[test]
+    fn test_process_tx_client_mismatch() {
+        let mut engine = Engine::new();
+
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(engine.process_tx(&tx).is_ok());
+
+        tx.client = 2;
+        tx.r#type = TransactionType::Dispute;
+        tx.amount = None;
+        assert_eq!(
+            engine.process_tx(&tx).unwrap_err(),
+            TxError::ClientMismatch { tx: 1, owner: 1 }
+        );
+        assert!(engine.account_snapshot(2).is_none());
+
+        tx.client = 1;
+        assert!(engine.process_tx(&tx).is_ok());
+        assert_eq!(engine.account_snapshot(1).unwrap().held, amount("5.0"));
+    }
+
+    #[test]
+    fn test_process_tx_global_duplicate() {
+        let mut engine = Engine::new();
+
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(engine.process_tx(&tx).is_ok());
+
+        tx.client = 2;
+        assert_eq!(engine.process_tx(&tx).unwrap_err(), TxError::DuplicateTx(1));
+        tx.r#type = TransactionType::Withdrawal;
+        assert_eq!(engine.process_tx(&tx).unwrap_err(), TxError::DuplicateTx(1));
+        assert!(engine.account_snapshot(2).is_none());
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let clients = u16::MAX as usize + 1;
+        let mut engine = Engine::with_capacity(clients);
+        let capacity = engine.ledger.users.capacity();
+        assert!(capacity >= clients);
+
+        for client in 0..=u16::MAX {
+            let tx = TransactionRequset {
+                r#type: TransactionType::Deposit,
+                client,
+                tx: client as u32,
+                amount: Some(amount("1.0")),
+                timestamp: None,
+                currency: None,
+            };
+            assert!(engine.process_tx(&tx).is_ok());
+        }
+
+        // Map was never reallocated
+        assert_eq!(engine.ledger.users.capacity(), capacity);
+        assert_eq!(engine.ledger.users.len(), clients);
+    }
+
+    #[test]
+    fn test_allowed_types() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+dispute,1,1,
+chargeback,1,1,
+";
+        let mut engine = Engine::new();
+        engine
+            .config
+            .allowed_types
+            .remove(&TransactionType::Chargeback);
+
+        let warnings = engine.process_reader(data.as_bytes()).unwrap();
+        assert_eq!(
+            warnings,
+            vec![ProcessingWarning {
+                line: 4,
+                kind: WarningKind::Rejected(TxError::TypeNotAllowed(TransactionType::Chargeback)),
+            }]
+        );
+        assert_eq!(engine.stats.rejected, 1);
+
+        let snapshot = engine.account_snapshot(1).unwrap();
+        assert!(!snapshot.locked);
+        assert_eq!(snapshot.held, amount("5.0"));
+    }
+
+    #[test]
+    fn test_withdrawal_chargeback() {
+        let data = "type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,4.0
+dispute,1,2,
+chargeback,1,2,
+";
+        let mut engine = Engine::new();
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+
+        let snapshot = engine.account_snapshot(1).unwrap();
+        assert_eq!(snapshot.total, amount("10.0"));
+        assert_eq!(snapshot.available, amount("10.0"));
+        assert!(snapshot.locked);
+        assert_eq!(engine.verify_conservation(), Ok(()));
+    }
+
+    #[test]
+    fn test_freeze_reason() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+dispute,1,1,
+chargeback,1,1,
+freeze,1,0,
+deposit,2,2,5.0
+freeze,2,0,
+";
+        let mut engine = Engine::new();
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+
+        // Administrative freeze doesn't relax chargeback lock
+        let snapshot = engine.account_snapshot(1).unwrap();
+        assert_eq!(snapshot.freeze_reason, FreezeReason::Chargeback);
+        assert!(snapshot.locked);
+        let snapshot = engine.account_snapshot(2).unwrap();
+        assert_eq!(snapshot.freeze_reason, FreezeReason::Administrative);
+
+        assert!(engine
+            .report()
+            .to_json_string()
+            .contains(r#""locked":true,"freeze_reason":"chargeback""#));
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "Transaction 3 of client 1 broke conservation")]
+    fn test_check_invariants_each_row() {
+        let mut engine = Engine::new();
+        engine.check_invariants_each_row = true;
+        let data = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,1.0\n";
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+
+        // Stands in for handler which forgets to record its transaction
+        engine.ledger.user_mut(1).account.total = amount("6.0");
+        let data = "type,client,tx,amount\ndeposit,2,4,1.0\ndeposit,1,3,1.0\n";
+        let _ = engine.process_reader(data.as_bytes());
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut engine = EngineBuilder::new().with_audit().build();
+        let data = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,1.0\n";
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+
+        engine.clear();
+        assert_eq!(engine.users().count(), 0);
+        assert!(engine.ledger.users.capacity() >= 2);
+        assert!(engine.ledger.tx_owners.capacity() >= 2);
+        assert_eq!(engine.ledger.sequence(), 0);
+        assert_eq!(engine.audit.as_ref().map(Vec::len), Some(0));
+
+        // Transaction ids may be reused in the next batch
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("5.0"));
+    }
+
+    /// Fuzz harness: arbitrary bytes and crafted rows must never panic
+    #[test]
+    fn test_process_bytes_never_panics() {
+        const TYPES: [&str; 12] = [
+            "deposit",
+            "withdrawal",
+            "dispute",
+            "resolve",
+            "chargeback",
+            "freeze",
+            "unfreeze",
+            "fee",
+            "interest",
+            "close",
+            "DEPOSIT",
+            "bogus",
+        ];
+        const AMOUNTS: [&str; 12] = [
+            "",
+            "1",
+            "0.0001",
+            "-1",
+            "922337203685477.5807",
+            "922337203685477",
+            "-922337203685477.5808",
+            "1e3",
+            "99999999999999999999",
+            "0.00005",
+            "1,5",
+            " 2 ",
+        ];
+        const CURRENCIES: [&str; 4] = ["", "USD", "eur", "X"];
+
+        // xorshift, deterministic across runs
+        let mut state = 0x9E37_79B9_7F4A_7C15_u64;
+        let mut next = move |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % n as u64) as usize
+        };
+
+        for round in 0..2_000 {
+            let mut data = Vec::new();
+            if round % 4 == 0 {
+                data.extend((0..next(200)).map(|_| next(256) as u8));
+            } else {
+                data.extend_from_slice(b"type,client,tx,amount,currency,timestamp\n");
+                for _ in 0..next(40) {
+                    let row = format!(
+                        "{},{},{},{},{},{}\n",
+                        TYPES[next(TYPES.len())],
+                        next(4),
+                        next(8),
+                        AMOUNTS[next(AMOUNTS.len())],
+                        CURRENCIES[next(CURRENCIES.len())],
+                        next(100),
+                    );
+                    data.extend_from_slice(row.as_bytes());
+                }
+                // Corrupt a few bytes
+                for _ in 0..next(3) {
+                    if !data.is_empty() {
+                        let i = next(data.len());
+                        data[i] = next(256) as u8;
+                    }
+                }
+            }
+
+            let mut engine = Engine::new();
+            engine.config.allow_redispute = round % 2 == 0;
+            engine.config.allow_unfreeze = round % 3 == 0;
+            engine.config.strict_timestamps = round % 5 == 0;
+            engine.input.has_headers = round % 4 != 0 || round % 8 == 0;
+            let _ = engine.process_bytes(&data);
+            let _ = engine.report().to_csv_string();
+            let _ = engine.verify_conservation();
+        }
+    }
+
+    #[test]
+    fn test_verify_conservation() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,1,2,3.0
+withdrawal,1,3,1.5
+dispute,1,2,
+chargeback,1,2,
+deposit,2,4,2.0
+withdrawal,2,5,1.0
+dispute,2,5,
+";
+        let mut engine = Engine::new();
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+        assert_eq!(engine.verify_conservation(), Ok(()));
+
+        engine.ledger.user_mut(2).account.total = amount("0.5");
+        assert_eq!(
+            engine.verify_conservation().unwrap_err(),
+            "Transactions moved 7.5000, but totals are 4.0000 and 3.0000 was charged back"
+        );
+
+        // Sums over clients may exceed single amount range
+        let big = amount("600000000000000");
+        let mut engine = EngineBuilder::new().with_max_amount(big).build();
+        for client in 1..=2 {
+            let tx = TransactionRequset {
+                r#type: TransactionType::Deposit,
+                client,
+                tx: client as u32,
+                amount: Some(big),
+                timestamp: None,
+                currency: None,
+            };
+            assert!(engine.process_tx(&tx).is_ok());
+        }
+        assert_eq!(engine.verify_conservation(), Ok(()));
+        engine.ledger.user_mut(2).account.total = Amount::ZERO;
+        assert_eq!(
+            engine.verify_conservation().unwrap_err(),
+            "Transactions moved 12000000000000000000 ten-thousandths, \
+             but totals are 600000000000000.0000 and 0.0000 was charged back"
+        );
+    }
+
+    #[test]
+    fn test_stop() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+dispute,1,1,
+chargeback,1,1,
+deposit,2,2,5.0
+";
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        let mut engine = EngineBuilder::new()
+            .with_on_freeze(move |_| flag.store(true, Ordering::Relaxed))
+            .build();
+        engine.stop = Some(stop);
+
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+        assert_eq!(engine.stats.rows, 3);
+        assert_eq!(
+            engine.report().to_csv_string(),
+            "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,true\n"
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let deposit = |client, tx, value| TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client,
+            tx,
+            amount: Some(amount(value)),
+            timestamp: None,
+            currency: None,
+        };
+
+        let mut first = Engine::new();
+        assert!(first.process_tx(&deposit(1, 1, "1.0")).is_ok());
+        assert!(first.process_tx(&deposit(3, 2, "2.0")).is_ok());
+
+        let mut second = Engine::new();
+        assert!(second.process_tx(&deposit(2, 3, "4.0")).is_ok());
+        assert!(second.process_tx(&deposit(3, 4, "8.0")).is_ok());
+        let dispute = TransactionRequset {
+            r#type: TransactionType::Dispute,
+            amount: None,
+            ..deposit(3, 4, "0.0")
+        };
+        assert!(second.process_tx(&dispute).is_ok());
+
+        let mut colliding = Engine::new();
+        assert!(colliding.process_tx(&deposit(4, 1, "1.0")).is_ok());
+        assert_eq!(first.merge(colliding).unwrap_err(), TxError::DuplicateTx(1));
+        assert!(first.user(4).is_none());
+
+        assert!(first.merge(second).is_ok());
+        assert_eq!(first.ledger.users.len(), 3);
+        let shared = first.account_snapshot(3).unwrap();
+        assert_eq!(shared.total, amount("10.0"));
+        assert_eq!(shared.held, amount("8.0"));
+        assert_eq!(shared.accepted_count, 3);
+        assert_eq!(first.totals().unwrap().total, amount("15.0"));
+        assert_eq!(first.ledger.tx_owners.len(), 4);
+
+        // Merged history can still be disputed
+        let resolve = TransactionRequset {
+            r#type: TransactionType::Resolve,
+            amount: None,
+            ..deposit(3, 4, "0.0")
+        };
+        assert!(first.process_tx(&resolve).is_ok());
+        assert_eq!(first.account_snapshot(3).unwrap().available, amount("10.0"));
+    }
+
+    #[test]
+    fn test_merge_stats() {
+        let dir = tempdir().unwrap();
+        let (first_path, second_path) =
+            (dir.path().join("first.csv"), dir.path().join("second.csv"));
+        std::fs::write(&first_path, "type,client,tx,amount\ndeposit,1,1,5.0\n").unwrap();
+        std::fs::write(
+            &second_path,
+            "type,client,tx,amount\ndeposit,2,2,3.0\nwithdrawal,2,3,9.0\ndispute,2,2,\n",
+        )
+        .unwrap();
+
+        let build = || {
+            let mut engine = EngineBuilder::new().with_audit().build();
+            engine.skip_processed_files = true;
+            engine
+        };
+        let mut first = build();
+        assert!(first.process_data(&first_path).is_ok());
+        let mut second = build();
+        assert_eq!(second.process_data(&second_path).unwrap().len(), 1);
+
+        assert!(first.merge(second).is_ok());
+        assert_eq!(
+            first.stats,
+            ProcessStats {
+                rows: 4,
+                accepted: 3,
+                rejected: 1,
+                parse_errors: 0,
+                skipped: 0,
+            }
+        );
+        let sequences: Vec<u64> = first.audit.iter().flatten().map(|e| e.sequence).collect();
+        assert_eq!(sequences, vec![1, 2, 4]);
+        assert_eq!(first.ledger.sequence(), 4);
+        let err = first.process_data(&second_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn test_merge_overflow() {
+        let big = amount("600000000000000");
+        let mut first = EngineBuilder::new().with_max_amount(big).build();
+        let data = "type,client,tx,amount,currency
+deposit,1,1,1.0,
+deposit,1,2,600000000000000,EUR
+";
+        assert!(first.process_bytes(data.as_bytes()).unwrap().is_empty());
+        let before = first.report().to_csv_string();
+
+        let mut second = EngineBuilder::new().with_max_amount(big).build();
+        let data = "type,client,tx,amount,currency
+deposit,2,3,1.0,
+deposit,1,4,2.0,
+deposit,1,5,600000000000000,EUR
+";
+        assert!(second.process_bytes(data.as_bytes()).unwrap().is_empty());
+
+        // Default balances fit, but EUR doesn't, so nothing is merged
+        assert_eq!(first.merge(second).unwrap_err(), TxError::Overflow);
+        assert_eq!(first.report().to_csv_string(), before);
+        assert!(first.user(2).is_none());
+        assert_eq!(first.ledger.tx_owners.len(), 2);
+    }
+
+    #[test]
+    fn test_on_freeze() {
+        let frozen = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = Engine::new();
+        let captured = frozen.clone();
+        engine.on_freeze = Some(Box::new(move |client| {
+            captured.lock().unwrap().push(client)
+        }));
+
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(engine.process_tx(&tx).is_ok());
+        tx.client = 2;
+        tx.tx = 2;
+        assert!(engine.process_tx(&tx).is_ok());
+
+        tx.amount = None;
+        tx.r#type = TransactionType::Dispute;
+        assert!(engine.process_tx(&tx).is_ok());
+        tx.r#type = TransactionType::Chargeback;
+        assert!(engine.process_tx(&tx).is_ok());
+        assert!(engine.process_tx(&tx).is_err());
+
+        // Chargebacks of already locked accounts don't lock them again
+        let data = "type,client,tx,amount
+deposit,3,3,1.0
+deposit,3,4,1.0
+dispute,3,3,
+dispute,3,4,
+chargeback,3,3,
+chargeback,3,4,
+deposit,4,5,1.0
+dispute,4,5,
+freeze,4,6,
+chargeback,4,5,
+";
+        assert!(engine.process_bytes(data.as_bytes()).unwrap().is_empty());
+        assert_eq!(
+            engine.user(4).unwrap().freeze_reason,
+            FreezeReason::Chargeback
+        );
+
+        assert_eq!(*frozen.lock().unwrap(), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_event_sink() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = Engine::new();
+        let captured = events.clone();
+        engine.set_event_sink(move |event| captured.lock().unwrap().push(event));
+
+        let data = "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,9.0\ndispute,1,1,\n";
+        assert_eq!(engine.process_reader(data.as_bytes()).unwrap().len(), 1);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                BalanceEvent {
+                    client: 1,
+                    tx: 1,
+                    currency: None,
+                    delta_total: amount("5.0"),
+                    delta_held: Amount::ZERO,
+                },
+                BalanceEvent {
+                    client: 1,
+                    tx: 1,
+                    currency: None,
+                    delta_total: Amount::ZERO,
+                    delta_held: amount("5.0"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_event_sink_overflow() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = Engine::new();
+        let captured = events.clone();
+        engine.set_event_sink(move |event| captured.lock().unwrap().push(event));
+        engine.ledger.user_mut(1).account.total = Amount::from_raw(i64::MIN);
+
+        let close = TransactionRequset {
+            r#type: TransactionType::Close,
+            client: 1,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+        };
+        assert!(engine.process_tx(&close).is_ok());
+        assert_eq!(engine.account_snapshot(1).unwrap().total, Amount::ZERO);
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_audit() {
+        let data = "type,client,tx,amount,currency
+deposit,1,1,5.0
+withdrawal,1,2,10.0
+withdrawal,1,3,1.5
+dispute,1,1,
+deposit,1,5,2.0,EUR
+";
+
+        let mut engine = Engine::new();
+        engine.audit = Some(Vec::new());
+        assert!(engine.process_reader(data.as_bytes()).is_ok());
+
+        assert_eq!(
+            engine.audit.unwrap(),
+            vec![
+                AuditEntry {
+                    sequence: 1,
+                    client: 1,
+                    tx: 1,
+                    tx_type: TransactionType::Deposit,
+                    amount: Some(amount("5.0")),
+                    currency: None,
+                    available: amount("5.0"),
+                    held: amount("0.0"),
+                },
+                AuditEntry {
+                    sequence: 3,
+                    client: 1,
+                    tx: 3,
+                    tx_type: TransactionType::Withdrawal,
+                    amount: Some(amount("1.5")),
+                    currency: None,
+                    available: amount("3.5"),
+                    held: amount("0.0"),
+                },
+                AuditEntry {
+                    sequence: 4,
+                    client: 1,
+                    tx: 1,
+                    tx_type: TransactionType::Dispute,
+                    amount: None,
+                    currency: None,
+                    available: amount("-1.5"),
+                    held: amount("5.0"),
+                },
+                AuditEntry {
+                    sequence: 5,
+                    client: 1,
+                    tx: 5,
+                    tx_type: TransactionType::Deposit,
+                    amount: Some(amount("2.0")),
+                    currency: Some("EUR".parse().unwrap()),
+                    available: amount("2.0"),
+                    held: amount("0.0"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_overdraft_limit() {
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.set_overdraft_limit(1, amount("-1.0")).unwrap_err(),
+            TxError::InvalidAmount
+        );
+        assert!(engine.set_overdraft_limit(1, amount("10.0")).is_ok());
+
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Withdrawal,
+            client: 1,
+            tx: 1,
+            amount: Some(amount("10.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(engine.process_tx(&tx).is_ok());
+        assert_eq!(
+            engine.account_snapshot(1).unwrap().available,
+            amount("-10.0")
+        );
+
+        tx.tx = 2;
+        tx.amount = Some(amount("0.01"));
+        assert_eq!(
+            engine.process_tx(&tx).unwrap_err(),
+            TxError::InsufficientFunds
+        );
+    }
+
+    #[test]
+    fn test_submit() {
+        let mut engine = Engine::new();
+
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 2,
+            tx: 1,
+            amount: Some(amount("4.5")),
+            timestamp: None,
+            currency: None,
+        };
+        let snapshot = engine.submit(&tx).unwrap();
+        assert_eq!(snapshot.client, 2);
+        assert_eq!(snapshot.total, amount("4.5"));
+        assert_eq!(snapshot.available, amount("4.5"));
+
+        tx.tx = 2;
+        tx.r#type = TransactionType::Withdrawal;
+        tx.amount = Some(amount("5.0"));
+        assert_eq!(engine.submit(&tx).unwrap_err(), TxError::InsufficientFunds);
+
+        // Snapshot of balance in request currency, also for its dispute
+        let eur = Some("EUR".parse().unwrap());
+        tx.tx = 3;
+        tx.r#type = TransactionType::Deposit;
+        tx.currency = eur;
+        let snapshot = engine.submit(&tx).unwrap();
+        assert_eq!((snapshot.currency, snapshot.total), (eur, amount("5.0")));
+        tx.r#type = TransactionType::Dispute;
+        tx.amount = None;
+        tx.currency = None;
+        let snapshot = engine.submit(&tx).unwrap();
+        assert_eq!((snapshot.currency, snapshot.held), (eur, amount("5.0")));
+        assert_eq!(engine.currency_snapshot(2, eur), Some(snapshot));
+        assert_eq!(engine.account_snapshot(2).unwrap().total, amount("4.5"));
+        assert_eq!(
+            engine.currency_snapshot(2, Some("USD".parse().unwrap())),
+            None
+        );
+    }
+
+    #[test]
+    fn test_account_snapshot() {
+        let mut engine = Engine::new();
+
+        let tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 7,
+            tx: 1,
+            amount: Some(amount("12.5")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(engine.process_tx(&tx).is_ok());
+
+        let snapshot = engine.account_snapshot(7).unwrap();
+        assert_eq!(snapshot.client, 7);
+        assert_eq!(snapshot.available, amount("12.5"));
+        assert_eq!(snapshot.held, amount("0.0"));
+        assert_eq!(snapshot.total, amount("12.5"));
+        assert!(!snapshot.locked);
+
+        assert!(engine.account_snapshot(8).is_none());
+    }
+
+    #[test]
+    fn test_accounts() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,3.0
+deposit,3,3,2.5
+dispute,2,2,
+chargeback,2,2,
+freeze,3,0,
+";
+
+        let mut engine = Engine::new();
+        assert!(engine.process_reader(data.as_bytes()).is_ok());
+
+        assert_eq!(engine.accounts().count(), 3);
+        assert_eq!(engine.accounts().filter(|a| a.locked).count(), 2);
+        let mut unlocked: Vec<u16> = engine
+            .accounts()
+            .filter(|a| !a.locked)
+            .map(|a| a.client)
+            .collect();
+        unlocked.sort_unstable();
+        assert_eq!(unlocked, vec![1]);
+    }
+
+    #[test]
+    fn test_totals() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,3.0
+deposit,3,3,2.5
+withdrawal,1,4,1.5
+dispute,2,2,
+dispute,3,3,
+chargeback,3,3,
+";
+
+        let mut engine = Engine::new();
+        assert!(engine.process_reader(data.as_bytes()).is_ok());
+
+        assert_eq!(
+            engine.totals(),
+            Ok(EngineTotals {
+                total_available: amount("3.5"),
+                total_held: amount("3.0"),
+                total: amount("6.5"),
+                frozen_accounts: 1,
+            })
+        );
+
+        // Each account is in range, but their sum isn't
+        let big = amount("600000000000000");
+        engine.ledger.user_mut(3).account.total = big;
+        engine.ledger.user_mut(4).account.total = big;
+        assert_eq!(engine.totals(), Err(TxError::Overflow));
+    }
+
+    #[test]
+    fn test_process_data() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("input.csv");
+        let mut file = std::fs::File::create(&path).unwrap();
+
+        let data = r#"type,client,tx,amount
+        deposit,    1,      1,  1.0
+        deposit,    2,      2,  2.0
+        deposit,    1,      3,  2.0
+        withdrawal, 1,      4,  1.5
+        withdrawal, 2,      5,  3.0"#;
+        write!(file, "{}", data).unwrap();
+
+        let mut engine = Engine::new();
+
+        assert!(engine.process_data(&path).is_ok());
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.avalible(),
+            amount("1.5")
+        );
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.held,
+            amount("0.0")
+        );
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.total,
+            amount("1.5")
+        );
+
+        assert_eq!(
+            engine.ledger.users.get(&2).unwrap().account.avalible(),
+            amount("2.0")
+        );
+        assert_eq!(
+            engine.ledger.users.get(&2).unwrap().account.held,
+            amount("0.0")
+        );
+        assert_eq!(
+            engine.ledger.users.get(&2).unwrap().account.total,
+            amount("2.0")
+        );
+    }
+
+    #[test]
+    fn test_process_files() {
+        let dir = tempdir().unwrap();
+        let first = dir.path().join("day1.csv");
+        let second = dir.path().join("day2.csv");
+        let whole = dir.path().join("all.csv");
+
+        let first_data = "deposit,1,1,5.0\ndeposit,2,2,3.0\nwithdrawal,1,3,1.0\n";
+        let second_data = "dispute,1,1,\ndeposit,2,1,1.0\nchargeback,1,1,\ndeposit,2,4,2.0\n";
+        let header = "type,client,tx,amount\n";
+        std::fs::write(&first, format!("{}{}", header, first_data)).unwrap();
+        std::fs::write(&second, format!("{}{}", header, second_data)).unwrap();
+        std::fs::write(&whole, format!("{}{}{}", header, first_data, second_data)).unwrap();
+
+        let mut engine = Engine::new();
+        assert!(engine.process_files(&[first, second]).is_ok());
+        let mut single = Engine::new();
+        assert!(single.process_data(&whole).is_ok());
+
+        assert_eq!(engine.report().accounts, single.report().accounts);
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("-1.0"));
+        // Tx id 1 from first file is not reused
+        assert_eq!(engine.account_snapshot(2).unwrap().total, amount("5.0"));
+
+        let missing = dir.path().join("missing.csv");
+        assert!(engine.process_files(&[missing]).is_err());
+    }
+
+    #[test]
+    fn test_validate_data() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("input.csv");
+        let data = "type,client,tx,amount
+deposit,1,2,1.0
+withdrawal,1,3,10.0
+dispute,1,2,
+dispute,2,9,
+";
+        std::fs::write(&path, data).unwrap();
+
+        let mut engine = Engine::new();
+        let tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(engine.process_tx(&tx).is_ok());
+
+        assert_eq!(
+            engine.validate_data(&path).unwrap(),
+            vec![(3, TxError::InsufficientFunds), (5, TxError::UnknownTx(9))]
+        );
 
+        assert_eq!(engine.ledger.users.len(), 1);
+        assert_eq!(engine.ledger.tx_owners.len(), 1);
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("5.0"));
+    }
+
+    #[test]
+    fn test_process_stats() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,1,1,5.0
+withdrawal,1,2,1.5
+bonus,1,3,1.0
+withdrawal,1,4,10.0
+dispute,1,2,
+";
+        let mut engine = Engine::new();
+        assert_eq!(engine.process_reader(data.as_bytes()).unwrap().len(), 3);
+        assert_eq!(
+            engine.stats,
+            ProcessStats {
+                rows: 6,
+                accepted: 3,
+                rejected: 2,
+                parse_errors: 1,
+                skipped: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_unknown_type() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+transfer,1,2,1.0
+deposit,1,3,1.0
+";
+        let mut engine = Engine::new();
+        let warnings = engine.process_bytes(data.as_bytes()).unwrap();
+        assert_eq!(
+            warnings,
+            vec![ProcessingWarning {
+                line: 3,
+                kind: WarningKind::UnknownType("transfer".to_string()),
+            }]
+        );
+        assert_eq!(engine.stats.parse_errors, 1);
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("6.0"));
+
+        let mut engine = Engine::new();
+        engine.input.strict_types = true;
+        let err = engine.process_bytes(data.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "Line 3: Unknown transaction type \"transfer\""
+        );
+    }
+
+    #[test]
+    fn test_id_limits() {
+        let mut engine = Engine::new();
+        let data = "type,client,tx,amount
+deposit,65535,4294967295,5.0
+deposit,70000,1,5.0
+deposit,1,4294967296,5.0
+";
+        let warnings = engine.process_bytes(data.as_bytes()).unwrap();
+
+        assert_eq!(engine.client_of_tx(u32::MAX), Some(u16::MAX));
+        assert_eq!(
+            engine.account_snapshot(u16::MAX).unwrap().total,
+            amount("5.0")
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                ProcessingWarning {
+                    line: 3,
+                    kind: WarningKind::Parse("Client id 70000 out of range 0..=65535".to_string()),
+                },
+                ProcessingWarning {
+                    line: 4,
+                    kind: WarningKind::Parse(
+                        "Transaction id 4294967296 out of range 0..=4294967295".to_string()
+                    ),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_process_bytes() {
+        let mut engine = Engine::new();
+        let warnings = engine
+            .process_bytes(b"type,client,tx,amount\ndeposit,1,1,5.0")
+            .unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(engine.account_snapshot(1).unwrap().available, amount("5.0"));
+    }
+
+    #[test]
+    fn test_open_disputes() {
+        let data = "type,client,tx,amount
+deposit,2,1,5.0
+deposit,1,2,3.0
+deposit,1,3,1.0
+withdrawal,1,4,2.0
+dispute,2,1,
+dispute,1,4,
+dispute,1,2,
+resolve,1,2,
+dispute,1,3,0.5
+";
+        let mut engine = Engine::new();
+        assert!(engine.process_bytes(data.as_bytes()).unwrap().is_empty());
+
+        assert_eq!(
+            engine.open_disputes(),
+            vec![
+                (1, 3, amount("0.5")),
+                (1, 4, amount("-2.0")),
+                (2, 1, amount("5.0"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_client_of_tx() {
+        let mut engine = Engine::new();
+        let data = "type,client,tx,amount\ndeposit,4,9,1.0\nwithdrawal,4,10,5.0\n";
+        engine.process_reader(data.as_bytes()).unwrap();
+
+        assert_eq!(engine.client_of_tx(9), Some(4));
+        // Rejected transactions are not indexed
+        assert_eq!(engine.client_of_tx(10), None);
+    }
+
+    #[test]
+    fn test_skip_processed_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("input.csv");
+        std::fs::write(&path, "type,client,tx,amount\ndeposit,1,1,5.0\n").unwrap();
+        let copy = dir.path().join("copy.csv");
+        std::fs::copy(&path, &copy).unwrap();
+
+        let mut engine = Engine::new();
+        engine.skip_processed_files = true;
+        assert!(engine.process_data(&path).unwrap().is_empty());
+        let err = engine.process_data(&copy).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("5.0"));
+
+        // Guard survives saved state
+        let mut state = Vec::new();
+        engine.save_state(&mut state).unwrap();
+        let mut engine = Engine::load_state(&mut state.as_slice()).unwrap();
+        engine.skip_processed_files = true;
+        assert!(engine.process_data(&path).is_err());
+    }
+
+    #[test]
+    fn test_process_data_gz() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("input.csv");
+        let gz_path = dir.path().join("input.csv.gz");
+
+        let data = "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,1.5\ndeposit,2,3,2.0\n";
+        std::fs::write(&path, data).unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&gz_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(data.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let mut engine = Engine::new();
         assert!(engine.process_data(&path).is_ok());
-        assert_eq!(engine.users.get(&1).unwrap().account.avalible(), 1.5);
-        assert_eq!(engine.users.get(&1).unwrap().account.held, 0.0);
-        assert_eq!(engine.users.get(&1).unwrap().account.total, 1.5);
+        let mut gz_engine = Engine::new();
+        assert!(gz_engine.process_data(&gz_path).is_ok());
+
+        for client in 1..=2 {
+            assert_eq!(
+                gz_engine.account_snapshot(client),
+                engine.account_snapshot(client)
+            );
+        }
+        assert_eq!(gz_engine.account_snapshot(1).unwrap().total, amount("3.5"));
+
+        // Truncated archive is reported instead of passing as complete input
+        let compressed = std::fs::read(&gz_path).unwrap();
+        std::fs::write(&gz_path, &compressed[..compressed.len() - 12]).unwrap();
+        let mut truncated = Engine::new();
+        assert_eq!(
+            truncated.process_data(&gz_path).unwrap(),
+            vec![ProcessingWarning {
+                line: 4,
+                kind: WarningKind::Skipped(
+                    "incomplete deflate stream, rest of input not read".to_string()
+                ),
+            }]
+        );
+        assert_eq!(truncated.stats.skipped, 1);
+        assert_eq!(truncated.account_snapshot(2), None);
+
+        let mut strict = Engine::new();
+        strict.input.stop_on_io_error = true;
+        let err = strict.process_data(&gz_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_process_data_parallel() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("input.csv");
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&path).unwrap());
+        writeln!(file, "type,client,tx,amount").unwrap();
+
+        // Simple LCG to generate reproducible shuffled input
+        let mut seed: u64 = 42;
+        let mut next = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            seed >> 33
+        };
+        let mut client_txs: HashMap<u16, Vec<u32>> = HashMap::new();
+        for tx in 1..=100_000u32 {
+            let client = (next() % 100) as u16 + 1;
+            let txs = client_txs.entry(client).or_default();
+            let old_tx = if txs.is_empty() {
+                None
+            } else {
+                Some(txs[next() as usize % txs.len()])
+            };
+            match (next() % 1000, old_tx) {
+                (0..=599, _) | (_, None) => {
+                    writeln!(
+                        file,
+                        "deposit,{},{},{}.{}",
+                        client,
+                        tx,
+                        next() % 100,
+                        next() % 10000
+                    )
+                    .unwrap();
+                    txs.push(tx);
+                }
+                (600..=849, _) => {
+                    writeln!(file, "withdrawal,{},{},{}", client, tx, next() % 100).unwrap();
+                    txs.push(tx);
+                }
+                (850..=929, Some(old)) => writeln!(file, "dispute,{},{},", client, old).unwrap(),
+                (930..=998, Some(old)) => writeln!(file, "resolve,{},{},", client, old).unwrap(),
+                (_, Some(old)) => writeln!(file, "chargeback,{},{},", client, old).unwrap(),
+            }
+        }
+        drop(file);
+
+        let mut serial = Engine::new();
+        assert!(serial.process_data(&path).is_ok());
+        let mut parallel = Engine::new();
+        assert!(parallel.process_data_parallel(&path, 4).is_ok());
+
+        assert_eq!(parallel.ledger.users.len(), 100);
+        assert_eq!(parallel.report().accounts, serial.report().accounts);
+        assert_eq!(parallel.ledger.tx_owners, serial.ledger.tx_owners);
+    }
+
+    #[test]
+    fn test_process_data_parallel_reused_tx() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("input.csv");
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,1,3.0
+dispute,2,1,
+deposit,2,2,1.0
+";
+        std::fs::write(&path, data).unwrap();
+
+        let mut serial = Engine::new();
+        let serial_warnings = serial.process_data(&path).unwrap();
+        let mut parallel = Engine::new();
+        let parallel_warnings = parallel.process_data_parallel(&path, 2).unwrap();
+
+        assert_eq!(parallel_warnings, serial_warnings);
+        assert_eq!(
+            parallel_warnings[0].kind,
+            WarningKind::Rejected(TxError::DuplicateTx(1))
+        );
+        assert_eq!(parallel.report().accounts, serial.report().accounts);
+        assert_eq!(parallel.stats, serial.stats);
+    }
+
+    #[test]
+    fn test_process_data_parallel_rejected_tx() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("input.csv");
+        // Rejected withdrawal doesn't take id reused by another client
+        let data = "type,client,tx,amount
+withdrawal,1,1,5.0
+deposit,2,1,3.0
+dispute,1,1,
+deposit,1,2,1.0
+deposit,2,2,1.0
+";
+        std::fs::write(&path, data).unwrap();
+
+        let mut serial = Engine::new();
+        let serial_warnings = serial.process_data(&path).unwrap();
+        assert_eq!(serial.account_snapshot(2).unwrap().total, amount("3.0"));
+        for num_threads in 1..=3 {
+            let mut parallel = Engine::new();
+            let parallel_warnings = parallel.process_data_parallel(&path, num_threads).unwrap();
+            assert_eq!(parallel_warnings, serial_warnings);
+            assert_eq!(parallel.report().accounts, serial.report().accounts);
+            assert_eq!(parallel.ledger.tx_owners, serial.ledger.tx_owners);
+        }
+    }
+
+    #[test]
+    fn test_process_data_parallel_options() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("input.csv");
+        let data = "type,client,tx,amount
+dispute,1,1,
+deposit,1,1,5.0
+deposit,2,2,3.0
+deposit,3,3,2.0
+dispute,2,2,
+chargeback,2,2,
+dispute,3,3,
+withdrawal,1,4,1.0
+resolve,3,3,
+dispute,4,9,
+";
+        std::fs::write(&path, data).unwrap();
+
+        let build = || {
+            let mut engine = EngineBuilder::new().with_audit().build();
+            engine.input.retry_unknown = true;
+            engine.skip_processed_files = true;
+            let (events, freezes) = (
+                Arc::new(Mutex::new(Vec::new())),
+                Arc::new(Mutex::new(Vec::new())),
+            );
+            let captured = events.clone();
+            engine.set_event_sink(move |event| captured.lock().unwrap().push(event));
+            let captured = freezes.clone();
+            engine.on_freeze = Some(Box::new(move |client| {
+                captured.lock().unwrap().push(client)
+            }));
+            (engine, events, freezes)
+        };
+        let (mut serial, serial_events, serial_freezes) = build();
+        let serial_warnings = serial.process_data(&path).unwrap();
+        assert_eq!(
+            serial_warnings,
+            vec![ProcessingWarning {
+                line: 11,
+                kind: WarningKind::Rejected(TxError::UnknownTx(9)),
+            }]
+        );
+        assert_eq!(*serial_freezes.lock().unwrap(), vec![2]);
+        assert_eq!(serial.audit.as_ref().unwrap().len(), 9);
+        assert_eq!(serial_events.lock().unwrap().len(), 9);
+
+        for num_threads in 1..=3 {
+            let (mut parallel, events, freezes) = build();
+            let warnings = parallel.process_data_parallel(&path, num_threads).unwrap();
+            assert_eq!(warnings, serial_warnings);
+            assert_eq!(parallel.report().accounts, serial.report().accounts);
+            assert_eq!(parallel.audit, serial.audit);
+            assert_eq!(*events.lock().unwrap(), *serial_events.lock().unwrap());
+            assert_eq!(*freezes.lock().unwrap(), *serial_freezes.lock().unwrap());
+            assert_eq!(parallel.stats, serial.stats);
+            assert_eq!(parallel.ledger.sequence(), serial.ledger.sequence());
+
+            let err = parallel
+                .process_data_parallel(&path, num_threads)
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        }
+    }
+
+    #[test]
+    fn test_process_reader_warnings() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,1,x,1.0
+withdrawal,1,2,1.5
+withdrawal,1,3,10.0
+";
+
+        let mut engine = Engine::new();
+        let warnings = engine.process_reader(data.as_bytes()).unwrap();
+
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].line, 3);
+        assert!(matches!(warnings[0].kind, WarningKind::Parse(_)));
+        assert_eq!(
+            warnings[1],
+            ProcessingWarning {
+                line: 5,
+                kind: WarningKind::Rejected(TxError::InsufficientFunds),
+            }
+        );
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("3.5"));
+    }
+
+    #[test]
+    fn test_warning_line_number() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,1,2,1.0
+deposit,x,3,1.0
+";
+        let mut engine = Engine::new();
+        let warnings = engine.process_reader(data.as_bytes()).unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0]
+            .to_string()
+            .starts_with("Line 4: Request parse error: "));
+    }
+
+    #[test]
+    fn test_process_reader_invalid_record() {
+        let data = b"type,client,tx,amount
+deposit,1,1,5.0
+deposit,1,2,\xff1.0
+deposit,1,3,1.0,extra
+withdrawal,1,4,1.5
+";
+
+        let mut engine = Engine::new();
+        let warnings = engine.process_reader(&data[..]).unwrap();
+
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].line, 3);
+        assert!(matches!(warnings[0].kind, WarningKind::Read(_)));
+        assert_eq!(warnings[1].line, 4);
+        assert!(matches!(warnings[1].kind, WarningKind::Read(_)));
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("3.5"));
+    }
+
+    #[test]
+    fn test_process_reader_non_finite_amount() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,1,2,NaN
+deposit,1,3,inf
+withdrawal,1,4,-inf
+";
+
+        let mut engine = Engine::new();
+        let warnings = engine.process_reader(data.as_bytes()).unwrap();
+
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings
+            .iter()
+            .all(|w| matches!(w.kind, WarningKind::Parse(_))));
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("5.0"));
+        assert_eq!(engine.ledger.tx_owners.len(), 1);
+    }
+
+    #[test]
+    fn test_process_reader_rounding() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.00005\ndeposit,2,2,1.00015\n";
+
+        let mut engine = Engine::new();
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("1.0"));
+        assert_eq!(engine.account_snapshot(2).unwrap().total, amount("1.0002"));
+
+        let mut engine = Engine::new();
+        engine.input.rounding = RoundingMode::HalfUp;
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("1.0001"));
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let data = "type,client,tx,amount,timestamp
+deposit,1,1,5.0,100
+deposit,2,2,1.0,50
+withdrawal,1,3,1.0,90
+deposit,1,4,1.0
+";
+        let mut lenient = Engine::new();
+        assert!(lenient.process_bytes(data.as_bytes()).unwrap().is_empty());
+        assert_eq!(lenient.account_snapshot(1).unwrap().total, amount("5.0"));
+
+        let mut strict = Engine::new();
+        strict.config.strict_timestamps = true;
+        assert_eq!(
+            strict.process_bytes(data.as_bytes()).unwrap(),
+            vec![ProcessingWarning {
+                line: 4,
+                kind: WarningKind::Rejected(TxError::OutOfOrder(90)),
+            }]
+        );
+        assert_eq!(strict.account_snapshot(1).unwrap().total, amount("6.0"));
+        assert_eq!(strict.user(1).unwrap().last_timestamp, Some(100));
+    }
+
+    #[test]
+    fn test_currencies() {
+        let data = "type,client,tx,amount,currency
+deposit,1,1,5.0,USD
+deposit,1,2,3.0,eur
+withdrawal,1,3,4.0,EUR
+dispute,1,1,
+deposit,2,4,1.0
+";
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.process_bytes(data.as_bytes()).unwrap(),
+            vec![ProcessingWarning {
+                line: 4,
+                kind: WarningKind::Rejected(TxError::InsufficientFunds),
+            }]
+        );
+
+        assert_eq!(
+            engine.report().to_csv_string(),
+            "client,available,held,total,locked,currency
+1,3.0000,0.0000,3.0000,false,EUR
+1,0.0000,5.0000,5.0000,false,USD
+2,1.0000,0.0000,1.0000,false,
+"
+        );
+    }
+
+    #[test]
+    fn test_rejected_currency() {
+        let mut engine = Engine::new();
+        let data = "type,client,tx,amount,currency\ndeposit,1,1,5.0,\n";
+        assert!(engine.process_bytes(data.as_bytes()).unwrap().is_empty());
+        let before = engine.report().to_csv_string();
+
+        let data = "type,client,tx,amount,currency\nwithdrawal,1,2,1.0,GBP\ndispute,1,3,,EUR\n";
+        let warnings = engine.process_bytes(data.as_bytes()).unwrap();
+        assert_eq!(
+            warnings.iter().map(|w| &w.kind).collect::<Vec<_>>(),
+            vec![
+                &WarningKind::Rejected(TxError::InsufficientFunds),
+                &WarningKind::Rejected(TxError::UnknownTx(3)),
+            ]
+        );
+        assert!(engine.user(1).unwrap().balances.is_empty());
+        assert_eq!(engine.report().to_csv_string(), before);
+    }
+
+    #[test]
+    fn test_process_reader_header() {
+        let data = "type,client,amount,tx\ndeposit,1,5.0,1\n";
+
+        let mut engine = Engine::new();
+        let err = engine.process_reader(data.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "Invalid CSV header: expected \"type,client,tx,amount\", found \"type,client,amount,tx\""
+        );
+        assert!(engine.ledger.users.is_empty());
+
+        let data = "deposit,1,1,5.0\nwithdrawal,1,2,1.5\n";
+        engine.input.has_headers = false;
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("3.5"));
+    }
+
+    #[test]
+    fn test_process_reader_delimiter() {
+        let data = "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,1.5\n";
+        let mut engine = Engine::new();
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+
+        let data = "type;client;tx;amount\ndeposit;1;1;5.0\nwithdrawal;1;2;1.5\n";
+        let mut semicolon = Engine::new();
+        semicolon.input.delimiter = b';';
+        assert!(semicolon
+            .process_reader(data.as_bytes())
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(semicolon.account_snapshot(1), engine.account_snapshot(1));
+    }
+
+    #[test]
+    fn test_process_reader_decimal_comma() {
+        let data = "type;client;tx;amount\ndeposit;1;1;1.001,50\nwithdrawal;1;2;1,5\n";
+        let mut engine = Engine::new();
+        engine.input.delimiter = b';';
+        engine.input.decimal_separator = DecimalSeparator::Comma;
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("1000.0"));
+    }
+
+    #[test]
+    fn test_process_reader_io_error() {
+        /// Fails `failures` times after returning first `ok` bytes
+        struct Flaky {
+            data: &'static [u8],
+            ok: usize,
+            failures: usize,
+        }
+        impl io::Read for Flaky {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.ok == 0 && self.failures > 0 {
+                    self.failures -= 1;
+                    return Err(io::Error::other("broken"));
+                }
+                let limit = if self.ok == 0 { buf.len() } else { self.ok };
+                let n = buf.len().min(limit).min(self.data.len());
+                buf[..n].copy_from_slice(&self.data[..n]);
+                self.data = &self.data[n..];
+                self.ok = self.ok.saturating_sub(n);
+                Ok(n)
+            }
+        }
+        let data = b"type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,1.5\ndeposit,1,3,1.0\n";
+        // Fails in the middle of the withdrawal record
+        let flaky = |failures| Flaky {
+            data,
+            ok: 45,
+            failures,
+        };
+
+        let mut engine = Engine::new();
+        let warnings = engine.process_reader(flaky(1)).unwrap();
+        assert_eq!(
+            warnings,
+            vec![ProcessingWarning {
+                line: 3,
+                kind: WarningKind::Skipped("broken".to_string()),
+            }]
+        );
+        assert_eq!(engine.stats.rows, 3);
+        assert_eq!(engine.stats.accepted, 2);
+        assert_eq!(engine.stats.skipped, 1);
+        assert_eq!(engine.stats.parse_errors, 0);
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("6.0"));
+
+        // Persistent error ends input without failing
+        let mut engine = Engine::new();
+        let warnings = engine.process_reader(flaky(usize::MAX)).unwrap();
+        assert_eq!(
+            warnings,
+            vec![ProcessingWarning {
+                line: 3,
+                kind: WarningKind::Skipped("broken, rest of input not read".to_string()),
+            }]
+        );
+        assert_eq!(engine.stats.rows, 2);
+        assert_eq!(engine.stats.skipped, 1);
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("5.0"));
+
+        let mut engine = Engine::new();
+        engine.input.stop_on_io_error = true;
+        let err = engine.process_reader(flaky(1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("5.0"));
+    }
+
+    #[test]
+    fn test_process_reader_bom_blank_lines() {
+        let data =
+            "\u{feff}type,client,tx,amount\ndeposit,1,1,5.0\n  \nwithdrawal,1,2,1.5\n\n \t \n";
+
+        let mut engine = Engine::new();
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("3.5"));
+        assert_eq!(engine.stats.rows, 2);
+
+        let data = "type,client,tx,amount\ndeposit,1,3,5.0,1\n";
+        let warnings = engine.process_reader(data.as_bytes()).unwrap();
+        assert_eq!(
+            warnings,
+            vec![ProcessingWarning {
+                line: 2,
+                kind: WarningKind::Read("found record with 5 fields, expected 4".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_process_reader_client_filter() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,5.0
+deposit,3,3,5.0
+";
+        let mut engine = Engine::new();
+        engine.input.ignore_clients.insert(2);
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+        assert!(engine.user(2).is_none());
+        assert_eq!(engine.ledger.users.len(), 2);
+        assert_eq!(engine.stats.rows, 2);
+
+        let mut engine = Engine::new();
+        engine.input.only_clients = Some([3].iter().copied().collect());
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+        assert_eq!(engine.ledger.users.len(), 1);
+        assert!(engine.user(3).is_some());
+    }
+
+    #[test]
+    fn test_process_reader_retry_unknown() {
+        let data = "type,client,tx,amount
+dispute,1,1,
+resolve,1,1,
+dispute,1,2,
+deposit,1,1,5.0
+dispute,1,3,
+deposit,1,3,2.0
+";
+        let mut engine = Engine::new();
+        engine.input.retry_unknown = true;
+        let warnings = engine.process_reader(data.as_bytes()).unwrap();
+
+        assert_eq!(
+            warnings,
+            vec![ProcessingWarning {
+                line: 4,
+                kind: WarningKind::Rejected(TxError::UnknownTx(2)),
+            }]
+        );
+        // Deferred rows are applied in input order
+        let snapshot = engine.account_snapshot(1).unwrap();
+        assert_eq!(snapshot.total, amount("7.0"));
+        assert_eq!(snapshot.held, amount("2.0"));
+    }
+
+    #[test]
+    fn test_process_reader() {
+        let data = "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,1.5\n";
+
+        let mut engine = Engine::new();
+
+        assert!(engine.process_reader(data.as_bytes()).is_ok());
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.total,
+            amount("3.5")
+        );
+        assert_eq!(
+            engine.ledger.users.get(&1).unwrap().account.held,
+            amount("0.0")
+        );
+    }
+
+    #[test]
+    fn test_save_load_state() {
+        let first_half = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,3.0
+withdrawal,1,3,1.5
+dispute,2,2,
+";
+        let second_half = "type,client,tx,amount
+resolve,2,2,
+dispute,1,1,
+chargeback,1,1,
+deposit,3,4,1.0
+deposit,1,1,1.0
+";
+
+        let mut straight = Engine::new();
+        assert!(straight.process_reader(first_half.as_bytes()).is_ok());
+        assert!(straight.process_reader(second_half.as_bytes()).is_ok());
+
+        let mut engine = Engine::new();
+        assert!(engine.process_reader(first_half.as_bytes()).is_ok());
+        let mut state = Vec::new();
+        assert!(engine.save_state(&mut state).is_ok());
+
+        let mut resumed = Engine::load_state(&mut &state[..]).unwrap();
+        let warnings = resumed.process_reader(second_half.as_bytes()).unwrap();
+
+        // Tx 1 is known after restore
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(resumed.report().accounts, straight.report().accounts);
+        assert_eq!(resumed.ledger.tx_owners, straight.ledger.tx_owners);
+        assert!(resumed.account_snapshot(1).unwrap().locked);
+
+        // States saved with capitalized names still load
+        let state = String::from_utf8(state).unwrap();
+        assert!(state.contains("\"state\":\"disputed\""));
+        let old = state.replace("\"state\":\"disputed\"", "\"state\":\"Disputed\"");
+        let mut resumed = Engine::load_state(&mut old.as_bytes()).unwrap();
+        assert_eq!(
+            resumed
+                .process_reader(second_half.as_bytes())
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(resumed.report().accounts, straight.report().accounts);
+    }
+
+    #[test]
+    fn test_write_report() {
+        let mut engine = Engine::new();
+
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 3,
+            tx: 1,
+            amount: Some(amount("10.5")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(engine.process_tx(&tx).is_ok());
+        tx.tx = 2;
+        tx.amount = Some(amount("2.0"));
+        assert!(engine.process_tx(&tx).is_ok());
+        tx.r#type = TransactionType::Dispute;
+        assert!(engine.process_tx(&tx).is_ok());
+
+        let mut buf = Vec::new();
+        assert!(engine.write_report(&mut buf).is_ok());
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,available,held,total,locked\n3,10.5000,2.0000,12.5000,false\n"
+        );
+
+        engine.output.precision = 2;
+        let mut buf = Vec::new();
+        assert!(engine.write_report(&mut buf).is_ok());
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,available,held,total,locked\n3,10.50,2.00,12.50,false\n"
+        );
+    }
+
+    #[test]
+    fn test_minor_units() {
+        let data = "type,client,tx,amount\ndeposit,1,1,150\ndeposit,1,2,1.5\n";
+        let mut engine = Engine::new();
+        engine.input.minor_units = Some(2);
+        let warnings = engine.process_reader(data.as_bytes()).unwrap();
+
+        assert_eq!(
+            warnings,
+            vec![ProcessingWarning {
+                line: 3,
+                kind: WarningKind::Parse("Invalid amount in minor units: \"1.5\"".to_string()),
+            }]
+        );
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("1.50"));
+    }
+
+    #[test]
+    fn test_decimals() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.2349\ndeposit,1,2,0.004\n";
+        let mut engine = EngineBuilder::new().with_decimals(2).unwrap().build();
+        let warnings = engine.process_reader(data.as_bytes()).unwrap();
+
+        // Rounded to zero
+        assert_eq!(
+            warnings[0].kind,
+            WarningKind::Rejected(TxError::InvalidAmount)
+        );
+        assert_eq!(engine.account_snapshot(1).unwrap().total, amount("1.23"));
+        assert_eq!(
+            engine.report().to_csv_string(),
+            "client,available,held,total,locked\n1,1.23,0.00,1.23,false\n"
+        );
+    }
+
+    #[test]
+    fn test_decimals_too_many() {
+        assert_eq!(
+            EngineBuilder::new().with_decimals(5).err(),
+            Some("Decimals 5 exceed supported 4".to_string())
+        );
+
+        // Set directly, checked before any row is read
+        let mut engine = Engine::new();
+        engine.input.decimals = 5;
+        let err = engine
+            .process_bytes(b"type,client,tx,amount\ndeposit,1,1,1.0\n")
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "Decimals 5 exceed supported 4");
+        assert!(engine.user(1).is_none());
+    }
+
+    #[test]
+    fn test_report_trim_trailing_zeros() {
+        let mut engine = EngineBuilder::new().with_trim_trailing_zeros(true).build();
+        let data = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,0.25\n";
+        assert!(engine.process_reader(data.as_bytes()).unwrap().is_empty());
+
+        assert_eq!(
+            engine.report().to_csv_string(),
+            "client,available,held,total,locked\n1,5,0,5,false\n2,0.25,0,0.25,false\n"
+        );
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_report_bincode_round_trip() {
+        let data = "type,client,tx,amount,currency
+deposit,1,1,5.25,
+deposit,1,2,1.5,EUR
+deposit,2,3,3.0,
+dispute,2,3,,
+chargeback,2,3,,
+withdrawal,3,4,1.0,
+";
+        let mut engine = Engine::new();
+        assert_eq!(engine.process_bytes(data.as_bytes()).unwrap().len(), 1);
+
+        let mut buf = Vec::new();
+        engine.write_report_bincode(&mut buf).unwrap();
+        assert_eq!(
+            Report::read_bincode(&mut buf.as_slice()).unwrap(),
+            engine.report().accounts
+        );
+
+        buf.pop();
+        assert_eq!(
+            Report::read_bincode(&mut buf.as_slice())
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_export_transactions() {
+        let data = "type,client,tx,amount
+deposit,2,3,1.0
+deposit,1,1,5.0
+deposit,1,2,3.0
+withdrawal,1,4,1.5
+dispute,1,1,
+dispute,1,2,
+resolve,1,2,
+dispute,2,3,
+chargeback,2,3,
+";
+        let config = Config {
+            withdrawal_fee_bps: 100,
+            ..Config::default()
+        };
+        let mut engine = EngineBuilder::new().with_config(config).build();
+        assert!(engine.process_bytes(data.as_bytes()).unwrap().is_empty());
+
+        let mut out = Vec::new();
+        engine.export_transactions(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,tx,type,amount,fee,state
+1,1,deposit,5.0000,0.0000,disputed
+1,2,deposit,3.0000,0.0000,resolved
+1,4,withdrawal,1.5000,0.0150,normal
+2,3,deposit,1.0000,0.0000,chargedback
+"
+        );
+    }
+
+    #[test]
+    fn test_report_csv_round_trip() {
+        let data = "type,client,tx,amount
+deposit,1,1,5.25
+deposit,2,2,3.0
+dispute,2,2,
+chargeback,2,2,
+deposit,3,3,1.0
+dispute,3,3,
+";
+        let mut engine = Engine::new();
+        assert!(engine.process_bytes(data.as_bytes()).unwrap().is_empty());
+        let report = engine.report();
+        let csv = report.to_csv_string();
+        assert!(csv.ends_with('\n'));
+
+        let mut rdr = csv::Reader::from_reader(csv.as_bytes());
+        assert_eq!(rdr.headers().unwrap(), &REPORT_HEADER[..]);
+        let rows: Vec<(u16, Amount, Amount, Amount, bool)> =
+            rdr.deserialize().map(Result::unwrap).collect();
+        assert_eq!(
+            rows,
+            report
+                .accounts
+                .iter()
+                .map(|s| (s.client, s.available, s.held, s.total, s.locked))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_stream_report() {
+        let mut engine = Engine::new();
+        for client in (0..1000).rev() {
+            let request = TransactionRequset {
+                r#type: TransactionType::Deposit,
+                client,
+                tx: client as u32,
+                amount: Some(amount("1.5")),
+                timestamp: None,
+                currency: None,
+            };
+            assert!(engine.process_tx(&request).is_ok());
+        }
+
+        let mut streamed = Vec::new();
+        engine.stream_report(&mut streamed, 64).unwrap();
+        assert_eq!(
+            String::from_utf8(streamed).unwrap(),
+            engine.report().to_csv_string()
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let mut engine = EngineBuilder::new().with_precision(2).build();
+        for (tx, client) in [2, 1].iter().enumerate() {
+            let request = TransactionRequset {
+                r#type: TransactionType::Deposit,
+                client: *client,
+                tx: tx as u32,
+                amount: Some(amount("1.5")),
+                timestamp: None,
+                currency: None,
+            };
+            assert!(engine.process_tx(&request).is_ok());
+        }
+
+        let report = engine.report();
+        assert_eq!(
+            report.accounts.iter().map(|a| a.client).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            report.to_csv_string(),
+            "client,available,held,total,locked\n1,1.50,0.00,1.50,false\n2,1.50,0.00,1.50,false\n"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json_string()).unwrap();
+        assert_eq!(json[1]["client"], 2);
+        assert_eq!(json[1]["total"], "1.5000");
+        assert_eq!(json[1]["locked"], false);
+    }
+
+    #[test]
+    fn test_client_report() {
+        let mut engine = Engine::new();
+        for client in 1..=3 {
+            let tx = TransactionRequset {
+                r#type: TransactionType::Deposit,
+                client,
+                tx: client as u32,
+                amount: Some(amount("1.0")),
+                timestamp: None,
+                currency: None,
+            };
+            assert!(engine.process_tx(&tx).is_ok());
+        }
+
+        assert_eq!(
+            engine.client_report(2).unwrap().to_csv_string(),
+            "client,available,held,total,locked\n2,1.0000,0.0000,1.0000,false\n"
+        );
+        assert!(engine.client_report(4).is_none());
+    }
+
+    #[test]
+    fn test_engine_builder() {
+        let mut engine = EngineBuilder::new()
+            .with_max_amount(amount("100.0"))
+            .with_precision(2)
+            .build();
+
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(amount("100.5")),
+            timestamp: None,
+            currency: None,
+        };
+        assert_eq!(engine.process_tx(&tx).unwrap_err(), TxError::AmountTooLarge);
+        tx.amount = Some(amount("10.125"));
+        assert!(engine.process_tx(&tx).is_ok());
+
+        let mut out = Vec::new();
+        engine.write_report(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\n1,10.13,0.00,10.13,false\n"
+        );
+    }
+
+    #[test]
+    fn test_write_report_sorted() {
+        let mut engine = Engine::new();
+
+        for (tx, client) in [3, 1, 2].iter().enumerate() {
+            let request = TransactionRequset {
+                r#type: TransactionType::Deposit,
+                client: *client,
+                tx: tx as u32,
+                amount: Some(amount("1.0")),
+                timestamp: None,
+                currency: None,
+            };
+            assert!(engine.process_tx(&request).is_ok());
+        }
+
+        let mut buf = Vec::new();
+        assert!(engine.write_report(&mut buf).is_ok());
+        let clients: Vec<&str> = std::str::from_utf8(&buf)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(clients, vec!["1", "2", "3"]);
+    }
+
+    #[test]
+    fn test_write_report_json() {
+        let mut engine = Engine::new();
+
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(amount("3.25")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(engine.process_tx(&tx).is_ok());
+        tx.client = 2;
+        tx.tx = 2;
+        tx.amount = Some(amount("7.0"));
+        assert!(engine.process_tx(&tx).is_ok());
+        tx.r#type = TransactionType::Dispute;
+        assert!(engine.process_tx(&tx).is_ok());
+
+        let mut buf = Vec::new();
+        assert!(engine.write_report_json(&mut buf).is_ok());
+        assert!(String::from_utf8_lossy(&buf).contains(r#""available":"3.2500""#));
 
-        assert_eq!(engine.users.get(&2).unwrap().account.avalible(), 2.0);
-        assert_eq!(engine.users.get(&2).unwrap().account.held, 0.0);
-        assert_eq!(engine.users.get(&2).unwrap().account.total, 2.0);
+        let snapshots: Vec<AccountSnapshot> = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(
+            snapshots,
+            vec![
+                AccountSnapshot {
+                    client: 1,
+                    available: amount("3.25"),
+                    held: amount("0.0"),
+                    total: amount("3.25"),
+                    locked: false,
+                    freeze_reason: FreezeReason::None,
+                    accepted_count: 1,
+                    rejected_count: 0,
+                    max_held: amount("0.0"),
+                    frozen_at: None,
+                    currency: None,
+                },
+                AccountSnapshot {
+                    client: 2,
+                    available: amount("0.0"),
+                    held: amount("7.0"),
+                    total: amount("7.0"),
+                    locked: false,
+                    freeze_reason: FreezeReason::None,
+                    accepted_count: 2,
+                    rejected_count: 0,
+                    max_held: amount("7.0"),
+                    frozen_at: None,
+                    currency: None,
+                },
+            ]
+        );
     }
 }
@@ -0,0 +1,124 @@
+use crate::user::TransactionType;
+use std::fmt;
+
+/// Reasons for which a transaction request can be rejected
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TxError {
+    /// Transaction id already used
+    DuplicateTx(u32),
+    /// Not enough available funds for withdrawal
+    InsufficientFunds,
+    /// Referenced transaction doesn't exist
+    UnknownTx(u32),
+    /// Account is locked
+    AccountFrozen,
+    /// Missing or invalid amount
+    InvalidAmount,
+    /// Amount exceeds configured limit
+    AmountTooLarge,
+    /// Amount is below configured minimum
+    AmountTooSmall,
+    /// Referenced transaction can't be disputed in its current state
+    NotDisputable(u32),
+    /// Referenced transaction is already under dispute
+    AlreadyDisputed(u32),
+    /// Referenced transaction is not under dispute
+    NotDisputed(u32),
+    /// Operation would overflow balance representation
+    Overflow,
+    /// Referenced transaction belongs to another client
+    ClientMismatch { tx: u32, owner: u16 },
+    /// Account is not locked
+    NotFrozen,
+    /// Account locked by chargeback can't be unlocked
+    UnfreezeNotAllowed,
+    /// Fee and interest requests are disabled
+    AdjustmentNotAllowed,
+    /// Requests of this type are disabled
+    TypeNotAllowed(TransactionType),
+    /// Account can't be closed while funds are held by dispute
+    FundsHeld,
+    /// Held funds don't match disputed transactions
+    HeldMismatch,
+    /// Request timestamp is earlier than last one of the client
+    OutOfOrder(u64),
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxError::DuplicateTx(tx) => write!(f, "Doubled transaction id {}", tx),
+            TxError::InsufficientFunds => write!(f, "Insufficient funds"),
+            TxError::UnknownTx(tx) => write!(f, "Invalid tx id {}", tx),
+            TxError::AccountFrozen => write!(f, "Account frozen"),
+            TxError::InvalidAmount => write!(f, "Invalid transaction amount"),
+            TxError::AmountTooLarge => write!(f, "Transaction amount exceeds limit"),
+            TxError::AmountTooSmall => write!(f, "Transaction amount below minimum"),
+            TxError::NotDisputable(tx) => write!(f, "Transaction {} can't be disputed", tx),
+            TxError::AlreadyDisputed(tx) => write!(f, "Transaction {} is already disputed", tx),
+            TxError::NotDisputed(tx) => write!(f, "Transaction {} is not disputed", tx),
+            TxError::Overflow => write!(f, "Amount overflow"),
+            TxError::ClientMismatch { tx, owner } => {
+                write!(f, "Transaction {} belongs to client {}", tx, owner)
+            }
+            TxError::NotFrozen => write!(f, "Account not frozen"),
+            TxError::UnfreezeNotAllowed => write!(f, "Account frozen by chargeback"),
+            TxError::AdjustmentNotAllowed => write!(f, "Fee and interest are disabled"),
+            TxError::TypeNotAllowed(t) => write!(f, "Transaction type {:?} is disabled", t),
+            TxError::FundsHeld => write!(f, "Account has held funds"),
+            TxError::HeldMismatch => write!(f, "Held funds don't match disputed transactions"),
+            TxError::OutOfOrder(t) => {
+                write!(f, "Timestamp {} is earlier than last one of the client", t)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TxError {}
+
+/// Reason why input record was skipped
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WarningKind {
+    /// Record couldn't be read from the input
+    Read(String),
+    /// Record couldn't be parsed into a request
+    Parse(String),
+    /// Record has transaction type not known to the engine
+    UnknownType(String),
+    /// Request was rejected by the engine
+    Rejected(TxError),
+    /// Reading input failed while record was read, so it was skipped
+    Skipped(String),
+}
+
+/// Problem found while processing single input record
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProcessingWarning {
+    /// 1-based line number in the input
+    pub line: u64,
+    pub kind: WarningKind,
+}
+
+impl fmt::Display for ProcessingWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            WarningKind::Read(e) => write!(f, "Line {}: Record read error: {}", self.line, e),
+            WarningKind::Parse(e) => write!(f, "Line {}: Request parse error: {}", self.line, e),
+            WarningKind::UnknownType(t) => {
+                write!(
+                    f,
+                    "Line {}: Unknown transaction type \"{}\". Skipped.",
+                    self.line, t
+                )
+            }
+            WarningKind::Rejected(e) => write!(f, "Line {}: {}. Ignored.", self.line, e),
+            WarningKind::Skipped(e) => {
+                write!(
+                    f,
+                    "Line {}: Record skipped after read error: {}",
+                    self.line, e
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::mem;
+
+use crate::config::Config;
+use crate::error::TxError;
+use crate::user::*;
+
+#[cfg(feature = "fxhash")]
+type IdHasher = rustc_hash::FxBuildHasher;
+#[cfg(not(feature = "fxhash"))]
+type IdHasher = std::collections::hash_map::RandomState;
+
+/// Map keyed by client or transaction id
+pub(crate) type IdMap<K, V> = HashMap<K, V, IdHasher>;
+
+/// Accounts of all clients and rules spanning them.
+/// Independent of input and output formats.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    pub(crate) users: IdMap<u16, User>,
+    /// Client owning each deposit/withdrawal transaction id.
+    /// Also used to reject transaction ids reused by another client.
+    /// Never evicted, global uniqueness costs memory for every transaction
+    /// ever processed, regardless of `Config::history_window`.
+    pub(crate) tx_owners: IdMap<u32, u16>,
+    /// Number of processed requests
+    #[serde(default)]
+    pub(crate) sequence: u64,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates ledger with room for `clients` accounts, avoiding rehashing while they are added
+    pub fn with_capacity(clients: usize) -> Self {
+        Ledger {
+            users: IdMap::with_capacity_and_hasher(clients, IdHasher::default()),
+            ..Default::default()
+        }
+    }
+
+    /// Removes all clients and transactions, keeping allocated capacity
+    pub fn clear(&mut self) {
+        self.users.clear();
+        self.tx_owners.clear();
+        self.sequence = 0;
+    }
+
+    /// Returns client account, if it exists
+    pub fn user(&self, client: u16) -> Option<&User> {
+        self.users.get(&client)
+    }
+
+    /// Iterates over all accounts in unspecified order
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+
+    /// Returns client account, creating it if needed
+    pub fn user_mut(&mut self, client: u16) -> &mut User {
+        self.users
+            .entry(client)
+            .or_insert_with(|| User::new(client))
+    }
+
+    /// Number of processed requests
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Checks if deposit or withdrawal with this id was processed
+    pub fn knows_tx(&self, tx: u32) -> bool {
+        self.tx_owners.contains_key(&tx)
+    }
+
+    /// Returns client owning deposit or withdrawal with this id
+    pub fn client_of_tx(&self, tx: u32) -> Option<u16> {
+        self.tx_owners.get(&tx).copied()
+    }
+
+    /// Validates request against all clients and passes it to its client
+    pub fn apply(&mut self, tx: &TransactionRequset, config: &Config) -> Result<(), TxError> {
+        self.sequence += 1;
+        let (tx_id, client, tx_type) = (tx.tx, tx.client, tx.r#type);
+
+        if !config.allowed_types.contains(&tx_type) {
+            return Err(TxError::TypeNotAllowed(tx_type));
+        }
+        Self::check_owner(&self.tx_owners, tx)?;
+
+        let sequence = self.sequence;
+        let user = self.user_mut(client);
+        let was_frozen = user.frozen();
+        let result = user.process_tx(tx, config);
+        match (was_frozen, user.frozen()) {
+            (false, true) => user.frozen_at = Some(sequence),
+            (true, false) => user.frozen_at = None,
+            _ => {}
+        }
+
+        // Id of rejected request kept in history is taken as well
+        let recorded = user.tx_history.contains_key(&tx_id);
+        if Self::creates_tx(tx_type) && recorded {
+            self.tx_owners.insert(tx_id, client);
+        }
+
+        result
+    }
+
+    /// Checks if request of this type uses new transaction id
+    pub(crate) fn creates_tx(tx_type: TransactionType) -> bool {
+        matches!(
+            tx_type,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Fee
+                | TransactionType::Interest
+                | TransactionType::Close
+        )
+    }
+
+    /// Checks request against owners of transaction ids.
+    /// New ids are unique across all clients and disputes must
+    /// reference transaction of the same client.
+    pub(crate) fn check_owner(
+        owners: &IdMap<u32, u16>,
+        tx: &TransactionRequset,
+    ) -> Result<(), TxError> {
+        match tx.r#type {
+            t if Self::creates_tx(t) => match owners.contains_key(&tx.tx) {
+                true => Err(TxError::DuplicateTx(tx.tx)),
+                false => Ok(()),
+            },
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                match owners.get(&tx.tx) {
+                    Some(&owner) if owner != tx.client => {
+                        Err(TxError::ClientMismatch { tx: tx.tx, owner })
+                    }
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Combines state of ledger which processed another part of input.
+    /// Fails without changes if both used the same transaction id or any balance would overflow.
+    pub fn merge(&mut self, other: Ledger) -> Result<(), TxError> {
+        if let Some(&tx) = other
+            .tx_owners
+            .keys()
+            .find(|tx| self.tx_owners.contains_key(tx))
+        {
+            return Err(TxError::DuplicateTx(tx));
+        }
+        for (client, user) in &other.users {
+            if let Some(existing) = self.users.get(client) {
+                existing.check_merge(user)?;
+            }
+        }
+
+        for (client, user) in other.users {
+            match self.users.entry(client) {
+                Entry::Occupied(mut e) => e.get_mut().merge(user)?,
+                Entry::Vacant(e) => {
+                    e.insert(user);
+                }
+            }
+        }
+        self.tx_owners.extend(other.tx_owners);
+        self.sequence += other.sequence;
+
+        Ok(())
+    }
+
+    /// Moves clients into `n` ledgers by `client % n`, leaving this one empty
+    pub fn split(&mut self, n: usize) -> Vec<Ledger> {
+        let mut shards: Vec<Ledger> = (0..n).map(|_| Ledger::new()).collect();
+        for (client, user) in mem::take(&mut self.users) {
+            shards[client as usize % n].users.insert(client, user);
+        }
+        for (tx, client) in mem::take(&mut self.tx_owners) {
+            shards[client as usize % n].tx_owners.insert(tx, client);
+        }
+        shards
+    }
+
+    /// Takes back clients of ledger produced by `split`.
+    /// Shard is expected to number requests continuing from this ledger.
+    pub fn absorb(&mut self, shard: Ledger) {
+        self.users.extend(shard.users);
+        self.tx_owners.extend(shard.tx_owners);
+        self.sequence = self.sequence.max(shard.sequence);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_apply() {
+        let config = Config::default();
+        let mut ledger = Ledger::new();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::from_int(5)),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(ledger.apply(&tx, &config).is_ok());
+        assert!(ledger.knows_tx(1));
+        assert_eq!(ledger.client_of_tx(1), Some(1));
+        assert_eq!(ledger.client_of_tx(2), None);
+
+        tx.client = 2;
+        assert_eq!(
+            ledger.apply(&tx, &config).unwrap_err(),
+            TxError::DuplicateTx(1)
+        );
+        tx.r#type = TransactionType::Dispute;
+        tx.amount = None;
+        assert_eq!(
+            ledger.apply(&tx, &config).unwrap_err(),
+            TxError::ClientMismatch { tx: 1, owner: 1 }
+        );
+
+        tx.client = 1;
+        assert!(ledger.apply(&tx, &config).is_ok());
+        assert_eq!(ledger.user(1).unwrap().account.held, Amount::from_int(5));
+        assert_eq!(ledger.sequence(), 4);
+    }
+
+    #[test]
+    fn test_rejected_tx_owned() {
+        let config = Config {
+            record_frozen_rejections: true,
+            ..Default::default()
+        };
+        let mut ledger = Ledger::new();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Freeze,
+            client: 1,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+        };
+        assert!(ledger.apply(&tx, &config).is_ok());
+
+        tx.r#type = TransactionType::Deposit;
+        tx.tx = 2;
+        tx.amount = Some(Amount::from_int(5));
+        assert_eq!(
+            ledger.apply(&tx, &config).unwrap_err(),
+            TxError::AccountFrozen
+        );
+        assert_eq!(ledger.client_of_tx(2), Some(1));
+
+        tx.client = 2;
+        assert_eq!(
+            ledger.apply(&tx, &config).unwrap_err(),
+            TxError::DuplicateTx(2)
+        );
+
+        // Rejected request which isn't recorded doesn't take its id
+        tx.tx = 3;
+        tx.amount = Some(Amount::ZERO);
+        assert_eq!(
+            ledger.apply(&tx, &config).unwrap_err(),
+            TxError::InvalidAmount
+        );
+        assert!(!ledger.knows_tx(3));
+    }
+
+    #[test]
+    fn test_frozen_at() {
+        let config = Config::default();
+        let mut ledger = Ledger::new();
+        let requests = [
+            (TransactionType::Deposit, 1, 1),
+            (TransactionType::Deposit, 2, 2),
+            (TransactionType::Deposit, 1, 3),
+            (TransactionType::Dispute, 1, 1),
+            (TransactionType::Chargeback, 1, 1),
+            (TransactionType::Freeze, 1, 1),
+            (TransactionType::Freeze, 2, 2),
+        ];
+        for &(r#type, client, tx) in requests.iter() {
+            let tx = TransactionRequset {
+                r#type,
+                client,
+                tx,
+                amount: Some(Amount::from_int(1)).filter(|_| r#type == TransactionType::Deposit),
+                timestamp: None,
+                currency: None,
+            };
+            assert!(ledger.apply(&tx, &config).is_ok());
+        }
+
+        // Later freeze of locked account doesn't move it
+        assert_eq!(ledger.user(1).unwrap().frozen_at, Some(5));
+        assert_eq!(ledger.user(2).unwrap().frozen_at, Some(7));
+    }
+
+    #[test]
+    fn test_split_absorb() {
+        let config = Config::default();
+        let mut ledger = Ledger::new();
+        for client in 0..4 {
+            let tx = TransactionRequset {
+                r#type: TransactionType::Deposit,
+                client,
+                tx: client as u32,
+                amount: Some(Amount::from_int(1)),
+                timestamp: None,
+                currency: None,
+            };
+            assert!(ledger.apply(&tx, &config).is_ok());
+        }
+
+        let shards = ledger.split(2);
+        assert_eq!(ledger.users().count(), 0);
+        assert!(shards[1].user(3).is_some() && shards[1].knows_tx(3));
+        assert!(shards[0].user(3).is_none());
+
+        for shard in shards {
+            ledger.absorb(shard);
+        }
+        assert_eq!(ledger.users().count(), 4);
+        assert!(ledger.knows_tx(2));
+    }
+}
@@ -0,0 +1,12 @@
+//! Transactions handling engine, processing CSV requests into client accounts
+
+pub mod config;
+pub mod engine;
+pub mod error;
+pub mod ledger;
+pub mod user;
+
+pub use config::{Config, InputFormat, OutputFormat};
+pub use engine::{AccountSnapshot, Engine, EngineBuilder, Report};
+pub use error::{ProcessingWarning, TxError, WarningKind};
+pub use user::{Account, Amount, TransactionRequset, TransactionType, User};
@@ -1,25 +1,182 @@
 use std::env;
-use std::{collections::HashMap, path::PathBuf};
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::process;
 
-mod engine;
-mod user;
-use engine::*;
+use accounting::Engine;
+
+const USAGE: &str = "Usage: accounting [OPTIONS] [INPUT]
+
+Processes transactions from INPUT CSV file, or from stdin when it's omitted,
+and prints state of client accounts.
+
+Options:
+  --format <csv|json>   Output format, csv by default
+  --delimiter <CHAR>    Input field separator, `tab` or `\\t` for tab
+  --client <ID>         Print only account of this client
+  --help                Print this message";
+
+/// Command line options
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    input: Option<PathBuf>,
+    json: bool,
+    delimiter: Option<u8>,
+    client: Option<u16>,
+    help: bool,
+}
+
+impl Args {
+    /// Parses arguments following program name
+    fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => parsed.help = true,
+                "--format" => match args.next().as_deref() {
+                    Some("json") => parsed.json = true,
+                    Some("csv") => parsed.json = false,
+                    other => return Err(format!("Unknown output format: {:?}", other)),
+                },
+                "--delimiter" => match args.next().as_deref() {
+                    Some("\\t") | Some("tab") => parsed.delimiter = Some(b'\t'),
+                    Some(d) if d.len() == 1 => parsed.delimiter = Some(d.as_bytes()[0]),
+                    other => return Err(format!("Invalid delimiter: {:?}", other)),
+                },
+                "--client" => match args.next().map(|c| c.parse::<u16>()) {
+                    Some(Ok(id)) => parsed.client = Some(id),
+                    other => return Err(format!("Invalid client id: {:?}", other)),
+                },
+                option if option.starts_with("--") => {
+                    return Err(format!("Unknown option: {}", option))
+                }
+                _ if parsed.input.is_some() => return Err(format!("Unexpected argument: {}", arg)),
+                _ => parsed.input = Some(PathBuf::from(arg)),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Prints message with usage hint and exits with usage error code
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\nTry `accounting --help` for usage.", message);
+    process::exit(2);
+}
+
+/// Prints fatal error and exits with failure code
+fn fail(error: &dyn fmt::Display) -> ! {
+    eprintln!("{}", error);
+    process::exit(1);
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
+    let args = Args::parse(env::args().skip(1)).unwrap_or_else(|e| usage_error(&e));
+    if args.help {
+        println!("{}", USAGE);
         return;
     }
+    // Waiting for typed input is rarely intended
+    if args.input.is_none() && io::stdin().is_terminal() {
+        usage_error("Missing input: pass INPUT file or pipe transactions to stdin");
+    }
+
+    let mut engine = Engine::new();
+    engine.print_warnings = true;
+    if let Some(delimiter) = args.delimiter {
+        engine.input.delimiter = delimiter;
+    }
+
+    #[cfg(feature = "interrupt")]
+    {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        if let Err(e) = ctrlc::set_handler(move || flag.store(true, Ordering::Relaxed)) {
+            eprintln!("{}", e);
+        }
+        engine.stop = Some(stop);
+    }
 
-    let mut engine = Engine {
-        users: HashMap::new(),
+    // Read from stdin when no input file is given
+    let result = match &args.input {
+        Some(path) => engine.process_data(path),
+        None => engine.process_reader(io::stdin().lock()),
     };
-    let path_buff = PathBuf::from(&args[1]);
 
-    if let Err(e) = engine.process_data(&path_buff) {
-        eprintln!("{}", e);
-        return;
+    if let Err(e) = result {
+        let source = match &args.input {
+            Some(path) => path.display().to_string(),
+            None => "stdin".to_string(),
+        };
+        fail(&format!("Failed to process {}: {}", source, e));
     }
 
-    engine.print_users();
+    let report = match args.client {
+        Some(id) => match engine.client_report(id) {
+            Some(report) => report,
+            None => fail(&format!("Client {} not found", id)),
+        },
+        None => engine.report(),
+    };
+    let mut stdout = io::stdout().lock();
+    let printed = if args.json {
+        report.write_json(&mut stdout)
+    } else {
+        report.write_csv(&mut stdout)
+    };
+    if let Err(e) = printed {
+        fail(&e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse(&[
+                "--format",
+                "json",
+                "in.csv",
+                "--client",
+                "7",
+                "--delimiter",
+                "tab"
+            ]),
+            Ok(Args {
+                input: Some(PathBuf::from("in.csv")),
+                json: true,
+                delimiter: Some(b'\t'),
+                client: Some(7),
+                help: false,
+            })
+        );
+        assert!(parse(&["--help"]).unwrap().help);
+        assert_eq!(parse(&[]), Ok(Args::default()));
+
+        assert!(parse(&["--client", "70000"])
+            .unwrap_err()
+            .starts_with("Invalid client id"));
+        assert_eq!(
+            parse(&["--verbose"]).unwrap_err(),
+            "Unknown option: --verbose"
+        );
+        assert_eq!(
+            parse(&["a.csv", "b.csv"]).unwrap_err(),
+            "Unexpected argument: b.csv"
+        );
+    }
 }
@@ -1,7 +1,397 @@
-use serde::Deserialize;
-use std::collections::HashMap;
+use crate::config::Config;
+use crate::error::TxError;
+use serde::de::{value, IntoDeserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::iter;
+use std::mem;
+use std::ops::{Add, Neg, Sub};
+use std::str::FromStr;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+/// Number of decimal places kept by `Amount`.
+pub const AMOUNT_DECIMALS: u32 = 4;
+const AMOUNT_SCALE: i64 = 10_i64.pow(AMOUNT_DECIMALS);
+/// Largest exponent magnitude accepted in scientific notation
+const MAX_EXPONENT: u32 = 20;
+
+/// Fixed-point money amount stored as ten-thousandths.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// Creates amount from raw ten-thousandths, e.g. `Amount::from_raw(12345)` is 1.2345
+    pub const fn from_raw(raw: i64) -> Self {
+        Amount(raw)
+    }
+
+    /// Raw ten-thousandths, inverse of `from_raw`
+    pub const fn raw(self) -> i64 {
+        self.0
+    }
+
+    /// Creates amount from whole units, e.g. `Amount::from_int(5)` is 5.0
+    pub const fn from_int(value: i64) -> Self {
+        Amount(value * AMOUNT_SCALE)
+    }
+
+    /// Checks that amounts with `decimals` decimal places can be stored
+    pub fn check_decimals(decimals: u32) -> Result<(), String> {
+        if decimals > AMOUNT_DECIMALS {
+            return Err(format!(
+                "Decimals {} exceed supported {}",
+                decimals, AMOUNT_DECIMALS
+            ));
+        }
+        Ok(())
+    }
+
+    /// Converts integer count of minor units, e.g. cents for `decimals` = 2.
+    /// `None` on overflow or when `decimals` exceeds `AMOUNT_DECIMALS`.
+    pub fn from_minor_units(units: i64, decimals: u32) -> Option<Amount> {
+        let scale = 10_i64.checked_pow(AMOUNT_DECIMALS.checked_sub(decimals)?)?;
+        units.checked_mul(scale).map(Amount)
+    }
+
+    /// Part of amount in basis points (1/100 of a percent), rounded half away from zero.
+    /// `None` on overflow.
+    pub fn basis_points(self, bps: u32) -> Option<Amount> {
+        let scaled = self.0 as i128 * bps as i128;
+        let rounded = (scaled + scaled.signum() * 5_000) / 10_000;
+        i64::try_from(rounded).ok().map(Amount)
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        Amount(self.0 + other.0)
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, other: Amount) -> Amount {
+        Amount(self.0 - other.0)
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Amount {
+        Amount(-self.0)
+    }
+}
+
+/// How digits beyond `AMOUNT_DECIMALS` are rounded when parsing amounts
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum RoundingMode {
+    /// Ties are rounded away from zero
+    HalfUp,
+    /// Ties are rounded to even last digit (banker's rounding)
+    #[default]
+    HalfEven,
+    /// Extra digits are dropped
+    Truncate,
+    /// Amounts with non-zero extra digits are invalid
+    Reject,
+}
+
+/// Character separating integer and fractional part of amounts.
+/// The other one of `.` and `,` may separate thousands.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum DecimalSeparator {
+    /// `1,234.5`
+    #[default]
+    Dot,
+    /// `1.234,5`, common in European feeds
+    Comma,
+}
+
+impl Amount {
+    /// Parses decimal string, e.g. `-12.5`, `0.0001`, `1,234.5` or `1.5e3`.
+    /// Digits beyond 4 decimal places are rounded with `rounding`.
+    pub fn parse(s: &str, rounding: RoundingMode) -> Result<Amount, String> {
+        Self::parse_scaled(s, AMOUNT_DECIMALS, rounding)
+    }
+
+    /// Parses decimal string keeping at most `decimals` decimal places,
+    /// which can't exceed `AMOUNT_DECIMALS`. Further digits are rounded with `rounding`.
+    pub fn parse_scaled(s: &str, decimals: u32, rounding: RoundingMode) -> Result<Amount, String> {
+        Self::parse_localized(s, decimals, rounding, DecimalSeparator::Dot)
+    }
+
+    /// Like `parse_scaled`, with decimal point given by `separator`
+    pub fn parse_localized(
+        s: &str,
+        decimals: u32,
+        rounding: RoundingMode,
+        separator: DecimalSeparator,
+    ) -> Result<Amount, String> {
+        let (point, thousands) = match separator {
+            DecimalSeparator::Dot => ('.', ','),
+            DecimalSeparator::Comma => (',', '.'),
+        };
+        Self::check_decimals(decimals)?;
+        // Smallest amount representable with `decimals`
+        let unit = 10_i64.pow(AMOUNT_DECIMALS - decimals);
+        let invalid = || format!("Invalid amount: {:?}", s);
+
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (mantissa, exponent) = match digits.split_once(['e', 'E']) {
+            Some((m, e)) => (m, e.parse::<i32>().map_err(|_| invalid())?),
+            None => (digits, 0),
+        };
+        let (int_part, frac_part) = match mantissa.split_once(point) {
+            Some((i, f)) => (i, f),
+            None => (mantissa, ""),
+        };
+
+        // Thousands separators must split integer part into groups of 3 digits
+        let int_part = if int_part.contains(thousands) {
+            let mut groups = int_part.split(thousands);
+            let first_valid = groups.next().is_some_and(|g| (1..=3).contains(&g.len()));
+            if !first_valid || !groups.all(|g| g.len() == 3) {
+                return Err(invalid());
+            }
+            int_part.replace(thousands, "")
+        } else {
+            int_part.to_string()
+        };
+
+        if int_part.is_empty() && frac_part.is_empty()
+            || !int_part.bytes().all(|b| b.is_ascii_digit())
+            || !frac_part.bytes().all(|b| b.is_ascii_digit())
+            || exponent.unsigned_abs() > MAX_EXPONENT
+        {
+            return Err(invalid());
+        }
+
+        // Exponent moves decimal point
+        let point = int_part.len() as i32 + exponent;
+        let all_digits = int_part + frac_part;
+        let (int_part, frac_part) = if point <= 0 {
+            (String::new(), "0".repeat(-point as usize) + &all_digits)
+        } else if point as usize >= all_digits.len() {
+            (
+                all_digits.clone() + &"0".repeat(point as usize - all_digits.len()),
+                String::new(),
+            )
+        } else {
+            let (i, f) = all_digits.split_at(point as usize);
+            (i.to_string(), f.to_string())
+        };
+        let (int_part, frac_part) = (int_part.as_str(), frac_part.as_str());
+
+        let (kept, dropped) = frac_part.split_at(frac_part.len().min(decimals as usize));
+
+        let int_value = if int_part.is_empty() {
+            0
+        } else {
+            int_part.parse::<i64>().map_err(|_| invalid())?
+        };
+        let frac_value = if kept.is_empty() {
+            0
+        } else {
+            let padding = 10_i64.pow(AMOUNT_DECIMALS - kept.len() as u32);
+            kept.parse::<i64>().map_err(|_| invalid())? * padding
+        };
+
+        let raw = int_value
+            .checked_mul(AMOUNT_SCALE)
+            .and_then(|v| v.checked_add(frac_value))
+            .ok_or_else(invalid)?;
+        if rounding == RoundingMode::Reject && dropped.bytes().any(|d| d != b'0') {
+            return Err(format!(
+                "Amount {:?} has more than {} decimal places",
+                s, decimals
+            ));
+        }
+        let raw = if Self::round_up(raw / unit, dropped, rounding) {
+            raw.checked_add(unit).ok_or_else(invalid)?
+        } else {
+            raw
+        };
+
+        Ok(Amount(if negative { -raw } else { raw }))
+    }
+
+    /// Decides if magnitude `kept` should be increased, given digits dropped after it
+    fn round_up(kept: i64, dropped: &str, rounding: RoundingMode) -> bool {
+        let mut digits = dropped.bytes();
+        let first = match digits.next() {
+            Some(d) => d,
+            None => return false,
+        };
+        let tie = first == b'5' && digits.all(|d| d == b'0');
+
+        match rounding {
+            RoundingMode::Truncate | RoundingMode::Reject => false,
+            RoundingMode::HalfUp => first >= b'5',
+            RoundingMode::HalfEven if tie => kept % 2 == 1,
+            RoundingMode::HalfEven => first >= b'5',
+        }
+    }
+}
+
+impl FromStr for Amount {
+    type Err = String;
+
+    /// Parses amount with default rounding
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Amount::parse(s, RoundingMode::default())
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display(AMOUNT_DECIMALS as usize).fmt(f)
+    }
+}
+
+/// Shows decimal value instead of raw ten-thousandths
+impl fmt::Debug for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Amount({})", self)
+    }
+}
+
+/// Three letter currency code, e.g. `EUR`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Currency([u8; 3]);
+
+impl FromStr for Currency {
+    type Err = String;
+
+    /// Parses code case insensitively
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            &[a, b, c] if s.bytes().all(|b| b.is_ascii_alphabetic()) => Ok(Currency([
+                a.to_ascii_uppercase(),
+                b.to_ascii_uppercase(),
+                c.to_ascii_uppercase(),
+            ])),
+            _ => Err(format!("Invalid currency: {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only ASCII letters are accepted
+        f.write_str(std::str::from_utf8(&self.0).expect("Currency is ASCII"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Formats amount with given number of decimal places
+pub struct AmountDisplay {
+    amount: Amount,
+    precision: usize,
+    trim: bool,
+}
+
+impl Amount {
+    /// Formats amount with `precision` decimal places, rounding half away from zero
+    pub fn display(self, precision: usize) -> AmountDisplay {
+        AmountDisplay {
+            amount: self,
+            precision,
+            trim: false,
+        }
+    }
+}
+
+impl AmountDisplay {
+    /// Skips trailing zeros of fraction, and the dot when nothing is left
+    pub fn trimmed(mut self, trim: bool) -> Self {
+        self.trim = trim;
+        self
+    }
+}
+
+impl fmt::Display for AmountDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kept = self.precision.min(AMOUNT_DECIMALS as usize);
+        let divisor = 10_u64.pow(AMOUNT_DECIMALS - kept as u32);
+        let abs = self.amount.0.unsigned_abs();
+        let rounded = abs / divisor + (abs % divisor * 2 >= divisor && divisor > 1) as u64;
+
+        let scale = 10_u64.pow(kept as u32);
+        let sign = if self.amount.0 < 0 && rounded != 0 {
+            "-"
+        } else {
+            ""
+        };
+        write!(f, "{}{}", sign, rounded / scale)?;
+        if self.trim {
+            let (mut fraction, mut digits) = (rounded % scale, kept);
+            while digits > 0 && fraction % 10 == 0 {
+                fraction /= 10;
+                digits -= 1;
+            }
+            if digits > 0 {
+                write!(f, ".{:0width$}", fraction, width = digits)?;
+            }
+        } else if self.precision > 0 {
+            write!(
+                f,
+                ".{:0width$}{:0<padding$}",
+                rounded % scale,
+                "",
+                width = kept,
+                padding = self.precision - kept
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum TransactionType {
     #[serde(rename = "deposit")]
     Deposit,
@@ -13,6 +403,44 @@ pub enum TransactionType {
     Resolve,
     #[serde(rename = "chargeback")]
     Chargeback,
+    #[serde(rename = "freeze")]
+    Freeze,
+    #[serde(rename = "unfreeze")]
+    Unfreeze,
+    /// Charge decreasing account total
+    #[serde(rename = "fee")]
+    Fee,
+    /// Credit increasing account total
+    #[serde(rename = "interest")]
+    Interest,
+    /// Withdraws whole remaining total and locks account
+    #[serde(rename = "close")]
+    Close,
+}
+
+impl TransactionType {
+    pub const ALL: [TransactionType; 10] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Freeze,
+        TransactionType::Unfreeze,
+        TransactionType::Fee,
+        TransactionType::Interest,
+        TransactionType::Close,
+    ];
+}
+
+impl FromStr for TransactionType {
+    type Err = String;
+
+    /// Parses lowercase name, as used in CSV input
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let deserializer: value::StrDeserializer<value::Error> = s.into_deserializer();
+        Self::deserialize(deserializer).map_err(|_| format!("Unknown transaction type \"{}\"", s))
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize)]
@@ -20,164 +448,798 @@ pub struct TransactionRequset {
     pub r#type: TransactionType,
     pub client: u16,
     pub tx: u32,
-    pub amount: Option<f64>,
+    pub amount: Option<Amount>,
+    /// Time of request, e.g. seconds since Unix epoch
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// Balance used by deposit, withdrawal, fee, interest and close,
+    /// `None` for the default one. Disputes follow referenced transaction.
+    #[serde(default)]
+    pub currency: Option<Currency>,
 }
 
-#[derive(Eq, PartialEq)]
+/// Serialized lowercase like `TransactionType`, capitalized names of older saved states are accepted
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum TransactionState {
+    #[serde(alias = "Normal")]
     Normal,
+    #[serde(alias = "Disputed")]
     Disputed,
+    /// Dispute was resolved, balances are as before the dispute
+    #[serde(alias = "Resolved")]
+    Resolved,
+    #[serde(alias = "Chargedback")]
     Chargedback,
+    /// Request rejected by locked account, kept only for audit
+    #[serde(alias = "Rejected")]
+    Rejected,
 }
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Transatcion {
     pub tx_type: TransactionType,
-    pub amount: f64,
+    pub amount: Amount,
     pub state: TransactionState,
+    /// Portion of amount under current dispute
+    #[serde(default)]
+    pub disputed: Amount,
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    /// Charged on top of withdrawal amount, not returned by its dispute
+    #[serde(default)]
+    pub fee: Amount,
+}
+
+impl Transatcion {
+    /// Checks if transaction may be disputed in its current state
+    pub fn is_disputable(&self, config: &Config) -> bool {
+        self.can_transition(TransactionState::Disputed, config)
+    }
+
+    /// Allowed state changes, the only place deciding which dispute
+    /// requests are valid. First of conflicting requests wins, e.g.
+    /// chargeback after resolve fails as transaction is not disputed.
+    pub fn can_transition(&self, to: TransactionState, config: &Config) -> bool {
+        use TransactionState::*;
+
+        if let TransactionType::Fee | TransactionType::Interest | TransactionType::Close =
+            self.tx_type
+        {
+            return false;
+        }
+        match (self.state, to) {
+            (Normal, Disputed) => true,
+            (Resolved, Disputed) => config.allow_redispute,
+            (Disputed, Resolved) | (Disputed, Chargedback) => true,
+            _ => false,
+        }
+    }
+
+    /// Amount by which the transaction changed account total.
+    /// Deposits increase it, withdrawals decrease it.
+    pub fn balance_effect(&self) -> Amount {
+        if self.state == TransactionState::Rejected {
+            return Amount::ZERO;
+        }
+        self.signed(self.amount) - self.fee
+    }
+
+    /// Amount held by dispute of this transaction.
+    /// Chargeback reverses it from account total.
+    pub fn disputed_effect(&self) -> Amount {
+        self.signed(self.disputed)
+    }
+
+    fn signed(&self, amount: Amount) -> Amount {
+        match self.tx_type {
+            TransactionType::Withdrawal | TransactionType::Fee | TransactionType::Close => -amount,
+            _ => amount,
+        }
+    }
 }
 
-#[derive(Default)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Account {
-    pub total: f64,
-    pub held: f64,
+    pub total: Amount,
+    pub held: Amount,
 }
 
 impl Account {
-    pub fn avalible(&self) -> f64 {
+    /// Total less held funds. Handlers keep it in range, see `checked_new`.
+    pub fn avalible(&self) -> Amount {
         self.total - self.held
     }
+
+    /// Account with given balances, `None` if available funds are out of range
+    pub fn checked_new(total: Amount, held: Amount) -> Option<Account> {
+        total.checked_sub(held)?;
+        Some(Account { total, held })
+    }
+
+    /// Sums balances of two accounts, `None` on overflow
+    pub fn checked_add(&self, other: &Account) -> Option<Account> {
+        Some(Account {
+            total: self.total.checked_add(other.total)?,
+            held: self.held.checked_add(other.held)?,
+        })
+    }
+
+    /// Panics if available funds are out of range
+    pub fn assert_invariants(&self) {
+        assert!(
+            Account::checked_new(self.total, self.held).is_some(),
+            "Available funds out of range: total {}, held {}",
+            self.total,
+            self.held
+        );
+    }
+}
+
+/// Cause of account lock, ordered by strictness
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+pub enum FreezeReason {
+    #[default]
+    #[serde(rename = "none")]
+    None,
+    /// Locked with freeze request, may always be unlocked
+    #[serde(rename = "administrative")]
+    Administrative,
+    #[serde(rename = "chargeback")]
+    Chargeback,
 }
 
-#[derive(Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct User {
     pub id: u16,
+    /// Balance in default currency
     pub account: Account,
+    /// Balances in other currencies
+    #[serde(default)]
+    pub balances: BTreeMap<Currency, Account>,
     pub tx_history: HashMap<u32, Transatcion>,
-    pub frozen: bool,
+    /// Deposits and withdrawals in processing order, tracked for history window
+    #[serde(default)]
+    pub history_order: VecDeque<u32>,
+    /// Why account is locked, if it is
+    #[serde(default)]
+    pub freeze_reason: FreezeReason,
+    /// How far below zero available funds may go on withdrawal
+    pub overdraft_limit: Amount,
+    /// Number of successfully processed requests
+    pub accepted_count: u64,
+    /// Number of rejected requests
+    pub rejected_count: u64,
+    /// Peak of held funds reached by disputes
+    #[serde(default)]
+    pub max_held: Amount,
+    /// Latest timestamp of accepted requests
+    #[serde(default)]
+    pub last_timestamp: Option<u64>,
+    /// Ledger sequence number of request which locked the account, while it's locked
+    #[serde(default)]
+    pub frozen_at: Option<u64>,
+    /// Disputed amounts summed by currency, in raw ten-thousandths.
+    /// Built from history on first check, then kept up to date by `process_tx`.
+    #[serde(skip)]
+    disputed_sums: Option<BTreeMap<Option<Currency>, i128>>,
+}
+
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "available {}, held {}, total {}",
+            self.avalible(),
+            self.held,
+            self.total
+        )
+    }
+}
+
+impl fmt::Display for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "client {}: {}", self.id, self.account)?;
+        if self.frozen() {
+            write!(f, ", locked")?;
+        }
+        Ok(())
+    }
 }
 
 impl User {
+    pub fn new(id: u16) -> Self {
+        User {
+            id,
+            ..Default::default()
+        }
+    }
+
+    /// Checks if account is locked
+    pub fn frozen(&self) -> bool {
+        self.freeze_reason != FreezeReason::None
+    }
+
+    /// Balance in `currency`, the default one for `None`
+    pub fn balance(&self, currency: Option<Currency>) -> Option<&Account> {
+        match currency {
+            None => Some(&self.account),
+            Some(c) => self.balances.get(&c),
+        }
+    }
+
+    /// Iterates over balances, starting with the default currency
+    pub fn all_balances(&self) -> impl Iterator<Item = (Option<Currency>, &Account)> {
+        iter::once((None, &self.account))
+            .chain(self.balances.iter().map(|(&c, account)| (Some(c), account)))
+    }
+
+    /// Currency of balance affected by request
+    pub fn currency_of(&self, tx: &TransactionRequset) -> Option<Currency> {
+        match tx.r#type {
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                self.tx_history
+                    .get(&tx.tx)
+                    .map_or(tx.currency, |t| t.currency)
+            }
+            TransactionType::Freeze | TransactionType::Unfreeze => None,
+            _ => tx.currency,
+        }
+    }
+
+    /// Checks that balances of `other` in every currency can be added to these
+    pub fn check_merge(&self, other: &User) -> Result<(), TxError> {
+        self.account
+            .checked_add(&other.account)
+            .ok_or(TxError::Overflow)?;
+        for (currency, account) in &other.balances {
+            let own = self.balances.get(currency).cloned().unwrap_or_default();
+            own.checked_add(account).ok_or(TxError::Overflow)?;
+        }
+        Ok(())
+    }
+
+    /// Adds state of the same client processed elsewhere, fails without changes on overflow.
+    /// Transaction ids of both must be distinct.
+    pub fn merge(&mut self, other: User) -> Result<(), TxError> {
+        self.check_merge(&other)?;
+        self.account = self
+            .account
+            .checked_add(&other.account)
+            .ok_or(TxError::Overflow)?;
+        for (currency, account) in other.balances {
+            let merged = self.balances.entry(currency).or_default();
+            *merged = merged.checked_add(&account).ok_or(TxError::Overflow)?;
+        }
+        self.tx_history.extend(other.tx_history);
+        self.history_order.extend(other.history_order);
+        self.freeze_reason = self.freeze_reason.max(other.freeze_reason);
+        self.overdraft_limit = self.overdraft_limit.max(other.overdraft_limit);
+        self.accepted_count += other.accepted_count;
+        self.rejected_count += other.rejected_count;
+        self.max_held = self.max_held.max(other.max_held);
+        self.last_timestamp = self.last_timestamp.max(other.last_timestamp);
+        self.frozen_at = match (self.frozen_at, other.frozen_at) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.disputed_sums = None;
+        Ok(())
+    }
+
     /// Entry point to processing requests
-    pub fn process_tx(&mut self, tx: TransactionRequset) -> Result<(), String> {
-        if self.frozen {
-            return Err("Account frozen".to_string());
+    pub fn process_tx(&mut self, tx: &TransactionRequset, config: &Config) -> Result<(), TxError> {
+        #[cfg(debug_assertions)]
+        let disputed_before = {
+            if self.disputed_sums.is_none() {
+                self.disputed_sums = Some(self.sum_disputed());
+            }
+            self.disputed_part(tx.tx)
+        };
+
+        // Handlers work on `account`, so balance in other currency takes its place
+        let result = match self.currency_of(tx) {
+            None => self.apply_tx(tx, config),
+            Some(currency) => {
+                let existing = self.balances.remove(&currency);
+                let existed = existing.is_some();
+                let mut account = existing.unwrap_or_default();
+                mem::swap(&mut self.account, &mut account);
+                let result = self.apply_tx(tx, config);
+                mem::swap(&mut self.account, &mut account);
+                // Rejected request doesn't open balance in new currency
+                if existed || result.is_ok() {
+                    self.balances.insert(currency, account);
+                }
+                result
+            }
+        };
+
+        #[cfg(debug_assertions)]
+        if result.is_ok() {
+            // Only the referenced transaction may change its dispute
+            let disputed_after = self.disputed_part(tx.tx);
+            if let Some(sums) = &mut self.disputed_sums {
+                let before = disputed_before.map(|(currency, part)| (currency, -part));
+                for (currency, part) in before.into_iter().chain(disputed_after) {
+                    *sums.entry(currency).or_default() += part;
+                }
+            }
+            self.assert_invariants();
+        }
+
+        match result {
+            Ok(()) => self.accepted_count += 1,
+            Err(_) => self.rejected_count += 1,
+        }
+
+        result
+    }
+
+    /// Dispatches request to its handler
+    fn apply_tx(&mut self, tx: &TransactionRequset, config: &Config) -> Result<(), TxError> {
+        let (tx_type, tx_id) = (tx.r#type, tx.tx);
+        if let (Some(timestamp), Some(last)) = (tx.timestamp, self.last_timestamp) {
+            if config.strict_timestamps && timestamp < last {
+                return Err(TxError::OutOfOrder(timestamp));
+            }
+        }
+        if config.strict_amounts
+            && tx.amount.is_some()
+            && matches!(
+                tx_type,
+                TransactionType::Dispute
+                    | TransactionType::Resolve
+                    | TransactionType::Chargeback
+                    | TransactionType::Close
+            )
+        {
+            return Err(TxError::InvalidAmount);
         }
 
         match tx.r#type {
-            TransactionType::Deposit => self.process_deposit(tx)?,
-            TransactionType::Withdrawal => self.process_withdrawal(tx)?,
-            TransactionType::Dispute => self.process_dispute(tx)?,
-            TransactionType::Resolve => self.process_resolve(tx)?,
-            TransactionType::Chargeback => self.process_chargeback(tx)?,
+            TransactionType::Deposit => self.process_deposit(tx, config)?,
+            TransactionType::Withdrawal => self.process_withdrawal(tx, config)?,
+            TransactionType::Dispute => self.process_dispute(tx, config)?,
+            TransactionType::Resolve => self.process_resolve(tx, config)?,
+            TransactionType::Chargeback => self.process_chargeback(tx, config)?,
+            TransactionType::Freeze => {
+                // Chargeback lock is stricter
+                self.freeze_reason = self.freeze_reason.max(FreezeReason::Administrative)
+            }
+            TransactionType::Unfreeze => self.process_unfreeze(config)?,
+            TransactionType::Fee | TransactionType::Interest => {
+                self.process_adjustment(tx, config)?
+            }
+            TransactionType::Close => self.process_close(tx)?,
         }
 
+        if config.history_window.is_some()
+            && matches!(
+                tx_type,
+                TransactionType::Deposit
+                    | TransactionType::Withdrawal
+                    | TransactionType::Fee
+                    | TransactionType::Interest
+                    | TransactionType::Close
+            )
+        {
+            self.history_order.push_back(tx_id);
+        }
+        self.evict_history(tx_id, config);
+        self.last_timestamp = self.last_timestamp.max(tx.timestamp);
+
         Ok(())
     }
 
+    /// Derives default currency balances from transaction history.
+    /// Matches `account` unless history was evicted, `None` if it's out of range.
+    pub fn recompute_account(&self) -> Option<Account> {
+        // History is unordered, so partial sums are kept wider than amounts
+        let (mut total, mut held) = (0_i128, 0_i128);
+        for t in self.tx_history.values().filter(|t| t.currency.is_none()) {
+            total += t.balance_effect().raw() as i128;
+            match t.state {
+                TransactionState::Disputed => held += t.disputed_effect().raw() as i128,
+                TransactionState::Chargedback => total -= t.disputed_effect().raw() as i128,
+                TransactionState::Normal
+                | TransactionState::Resolved
+                | TransactionState::Rejected => {}
+            }
+        }
+        Account::checked_new(
+            Amount::from_raw(i64::try_from(total).ok()?),
+            Amount::from_raw(i64::try_from(held).ok()?),
+        )
+    }
+
+    /// Panics if account balances don't match transaction history.
+    /// Held funds may be negative when withdrawal is disputed,
+    /// so they are checked against disputed transactions instead.
+    pub fn assert_invariants(&self) {
+        let scanned;
+        let sums = match &self.disputed_sums {
+            Some(sums) => sums,
+            None => {
+                scanned = self.sum_disputed();
+                &scanned
+            }
+        };
+        for (currency, account) in self.all_balances() {
+            account.assert_invariants();
+            assert_eq!(
+                sums.get(&currency).copied().unwrap_or(0),
+                account.held.raw() as i128,
+                "Held funds don't match disputed transactions of client {}",
+                self.id
+            );
+        }
+    }
+
+    /// Sums disputed amounts of whole history by currency
+    fn sum_disputed(&self) -> BTreeMap<Option<Currency>, i128> {
+        let mut sums = BTreeMap::new();
+        for t in self.tx_history.values() {
+            if t.state == TransactionState::Disputed {
+                *sums.entry(t.currency).or_default() += t.disputed_effect().raw() as i128;
+            }
+        }
+        sums
+    }
+
+    /// Currency and disputed amount of transaction, if it's under dispute
+    #[cfg(debug_assertions)]
+    fn disputed_part(&self, tx: u32) -> Option<(Option<Currency>, i128)> {
+        self.tx_history
+            .get(&tx)
+            .filter(|t| t.state == TransactionState::Disputed)
+            .map(|t| (t.currency, t.disputed_effect().raw() as i128))
+    }
+
+    /// Drops transactions which can't or no longer need to be disputed
+    fn evict_history(&mut self, tx: u32, config: &Config) {
+        if config.evict_chargedback
+            && self
+                .tx_history
+                .get(&tx)
+                .is_some_and(|t| t.state == TransactionState::Chargedback)
+        {
+            self.tx_history.remove(&tx);
+        }
+
+        let Some(window) = config.history_window else {
+            return;
+        };
+        // Disputed transactions are requeued, so each entry is visited at most once
+        let mut remaining = self.history_order.len();
+        while self.history_order.len() > window && remaining > 0 {
+            remaining -= 1;
+            let Some(id) = self.history_order.pop_front() else {
+                break;
+            };
+            match self.tx_history.get(&id) {
+                Some(t) if t.state == TransactionState::Disputed => {
+                    self.history_order.push_back(id)
+                }
+                Some(_) => {
+                    self.tx_history.remove(&id);
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Rejects request of locked account, recording it in history if configured
+    fn reject_frozen(&mut self, tx: &TransactionRequset, config: &Config) -> TxError {
+        if config.record_frozen_rejections {
+            if let Entry::Vacant(e) = self.tx_history.entry(tx.tx) {
+                e.insert(Transatcion {
+                    tx_type: tx.r#type,
+                    amount: tx.amount.unwrap_or(Amount::ZERO),
+                    state: TransactionState::Rejected,
+                    disputed: Amount::ZERO,
+                    currency: tx.currency,
+                    fee: Amount::ZERO,
+                });
+                if config.history_window.is_some() {
+                    self.history_order.push_back(tx.tx);
+                }
+            }
+        }
+        TxError::AccountFrozen
+    }
+
+    /// Returns request amount if it is present, positive and within limits
+    fn validate_amount(tx: &TransactionRequset, config: &Config) -> Result<Amount, TxError> {
+        match tx.amount {
+            Some(v) if v <= Amount::ZERO => Err(TxError::InvalidAmount),
+            Some(v) if v > config.max_amount => Err(TxError::AmountTooLarge),
+            Some(v) if v < config.min_amount => Err(TxError::AmountTooSmall),
+            Some(v) => Ok(v),
+            None => Err(TxError::InvalidAmount),
+        }
+    }
+
     /// Processes deposit request
-    fn process_deposit(&mut self, tx: TransactionRequset) -> Result<(), String> {
+    fn process_deposit(&mut self, tx: &TransactionRequset, config: &Config) -> Result<(), TxError> {
+        if self.frozen() {
+            return Err(self.reject_frozen(tx, config));
+        }
         if self.tx_history.contains_key(&tx.tx) {
-            return Err(format!("Doubled transaction id. Ignored.\n{:?}", tx));
+            return Err(TxError::DuplicateTx(tx.tx));
         }
 
-        if tx.amount.is_none() || *tx.amount.as_ref().unwrap() == 0.0 {
-            return Err(format!("Invalid transaction data. Ignored.\n{:?}", tx));
-        }
+        let amount = Self::validate_amount(tx, config)?;
+
+        let total = self
+            .account
+            .total
+            .checked_add(amount)
+            .ok_or(TxError::Overflow)?;
+        self.set_balances(total, self.account.held)?;
 
         self.tx_history.insert(
             tx.tx,
             Transatcion {
                 tx_type: tx.r#type,
-                amount: tx.amount.clone().unwrap(),
+                amount,
                 state: TransactionState::Normal,
+                disputed: Amount::ZERO,
+                currency: tx.currency,
+                fee: Amount::ZERO,
             },
         );
 
-        self.account.total += tx.amount.unwrap();
-
         Ok(())
     }
 
     /// Processes withdrawal request
-    fn process_withdrawal(&mut self, tx: TransactionRequset) -> Result<(), String> {
+    fn process_withdrawal(
+        &mut self,
+        tx: &TransactionRequset,
+        config: &Config,
+    ) -> Result<(), TxError> {
+        if self.frozen() {
+            return Err(self.reject_frozen(tx, config));
+        }
         if self.tx_history.contains_key(&tx.tx) {
-            return Err(format!("Doubled transaction id. Ignored.\n{:?}", tx));
+            return Err(TxError::DuplicateTx(tx.tx));
         }
 
-        let amount = if tx.amount.is_none() || *tx.amount.as_ref().unwrap() == 0.0 {
-            return Err(format!("Invalid transaction data. Ignored.\n{:?}", tx));
-        } else {
-            tx.amount.unwrap()
-        };
+        let amount = Self::validate_amount(tx, config)?;
+        let fee = amount
+            .basis_points(config.withdrawal_fee_bps)
+            .ok_or(TxError::Overflow)?;
+        let charged = amount.checked_add(fee).ok_or(TxError::Overflow)?;
 
-        if amount > self.account.avalible() {
-            return Err("Insufficient funds.".to_string());
+        let spendable = self
+            .account
+            .avalible()
+            .checked_add(self.overdraft_limit)
+            .ok_or(TxError::Overflow)?;
+        if charged > spendable {
+            return Err(TxError::InsufficientFunds);
         }
 
+        let total = self
+            .account
+            .total
+            .checked_sub(charged)
+            .ok_or(TxError::Overflow)?;
+        self.set_balances(total, self.account.held)?;
+
         self.tx_history.insert(
             tx.tx,
             Transatcion {
                 tx_type: tx.r#type,
-                amount: tx.amount.clone().unwrap(),
+                amount,
                 state: TransactionState::Normal,
+                disputed: Amount::ZERO,
+                currency: tx.currency,
+                fee,
             },
         );
 
-        self.account.total -= tx.amount.unwrap();
-
         Ok(())
     }
 
     /// Processes dispute request
-    fn process_dispute(&mut self, tx: TransactionRequset) -> Result<(), String> {
+    fn process_dispute(&mut self, tx: &TransactionRequset, config: &Config) -> Result<(), TxError> {
         let old_tx = match self.tx_history.get_mut(&tx.tx) {
-            None => return Err(format!("Invalid tx id. Ignored.\n{:?}", tx)),
+            None => return Err(TxError::UnknownTx(tx.tx)),
             Some(v) => v,
         };
 
-        if old_tx.state != TransactionState::Normal {
-            return Err(format!(
-                "Transaction can't be dispputed. Ignored.\n{:?}",
-                tx
-            ));
-        } else {
-            old_tx.state = TransactionState::Disputed;
-            self.account.held += old_tx.amount;
-            Ok(())
+        if old_tx.state == TransactionState::Disputed {
+            return Err(TxError::AlreadyDisputed(tx.tx));
         }
+        if !old_tx.is_disputable(config) {
+            return Err(TxError::NotDisputable(tx.tx));
+        }
+
+        // Only part of the transaction may be disputed
+        let disputed = match tx.amount {
+            None => old_tx.amount,
+            Some(v) if v > Amount::ZERO => v.min(old_tx.amount),
+            Some(_) => return Err(TxError::InvalidAmount),
+        };
+
+        let held = self
+            .account
+            .held
+            .checked_add(old_tx.signed(disputed))
+            .ok_or(TxError::Overflow)?;
+        self.set_balances(self.account.total, held)?;
+
+        let old_tx = self.tx_history.get_mut(&tx.tx).expect("Checked above");
+        old_tx.disputed = disputed;
+        old_tx.state = TransactionState::Disputed;
+        self.max_held = self.max_held.max(self.account.held);
+        Ok(())
     }
 
     /// Processes resolve request
-    fn process_resolve(&mut self, tx: TransactionRequset) -> Result<(), String> {
-        let old_tx = match self.tx_history.get_mut(&tx.tx) {
-            None => return Err(format!("Invalid tx id. Ignored.\n{:?}", tx)),
-            Some(v) => v,
+    fn process_resolve(&mut self, tx: &TransactionRequset, config: &Config) -> Result<(), TxError> {
+        let effect = match self.tx_history.get(&tx.tx) {
+            None => return Err(TxError::UnknownTx(tx.tx)),
+            Some(t) if !t.can_transition(TransactionState::Resolved, config) => {
+                return Err(TxError::NotDisputed(tx.tx))
+            }
+            Some(t) => t.disputed_effect(),
         };
 
-        if old_tx.state != TransactionState::Disputed {
-            return Err(format!("Transaction can't be resolved. Ignored.\n{:?}", tx));
-        } else {
-            old_tx.state = TransactionState::Normal;
-            self.account.held -= old_tx.amount;
-            Ok(())
-        }
+        let held = self.release_held(tx.tx, effect)?;
+        self.set_balances(self.account.total, held)?;
+        let old_tx = self.tx_history.get_mut(&tx.tx).expect("Checked above");
+        old_tx.state = TransactionState::Resolved;
+        old_tx.disputed = Amount::ZERO;
+        Ok(())
     }
 
     /// Processes chargeback request
-    fn process_chargeback(&mut self, tx: TransactionRequset) -> Result<(), String> {
-        let old_tx = match self.tx_history.get_mut(&tx.tx) {
-            None => return Err(format!("Invalid tx id. Ignored.\n{:?}", tx)),
-            Some(v) => v,
+    fn process_chargeback(
+        &mut self,
+        tx: &TransactionRequset,
+        config: &Config,
+    ) -> Result<(), TxError> {
+        let effect = match self.tx_history.get(&tx.tx) {
+            None => return Err(TxError::UnknownTx(tx.tx)),
+            Some(t) if !t.can_transition(TransactionState::Chargedback, config) => {
+                return Err(TxError::NotDisputed(tx.tx))
+            }
+            Some(t) => t.disputed_effect(),
         };
 
-        if old_tx.state != TransactionState::Disputed {
-            return Err(format!("Transaction can't be resolved. Ignored.\n{:?}", tx));
-        } else {
-            old_tx.state = TransactionState::Chargedback;
-            self.account.held -= old_tx.amount;
-            self.account.total -= old_tx.amount;
-            self.frozen = true;
-            Ok(())
+        let held = self.release_held(tx.tx, effect)?;
+        let total = self
+            .account
+            .total
+            .checked_sub(effect)
+            .ok_or(TxError::Overflow)?;
+
+        self.set_balances(total, held)?;
+        self.tx_history
+            .get_mut(&tx.tx)
+            .expect("Checked above")
+            .state = TransactionState::Chargedback;
+        if config.freeze_on_chargeback {
+            self.freeze_reason = FreezeReason::Chargeback;
+        }
+        Ok(())
+    }
+
+    /// Replaces balances, unless available funds would be out of range
+    fn set_balances(&mut self, total: Amount, held: Amount) -> Result<(), TxError> {
+        self.account = Account::checked_new(total, held).ok_or(TxError::Overflow)?;
+        Ok(())
+    }
+
+    /// Returns held funds left after dispute of `tx` with `effect` ends.
+    /// Held funds may change sign only if other disputes account for it,
+    /// otherwise they don't match disputed transactions.
+    fn release_held(&self, tx: u32, effect: Amount) -> Result<Amount, TxError> {
+        let held = self
+            .account
+            .held
+            .checked_sub(effect)
+            .ok_or(TxError::Overflow)?;
+
+        let crosses_zero = effect > Amount::ZERO && held < Amount::ZERO
+            || effect < Amount::ZERO && held > Amount::ZERO;
+        if crosses_zero {
+            let currency = self.tx_history.get(&tx).and_then(|t| t.currency);
+            let others = self
+                .tx_history
+                .iter()
+                .filter(|&(&id, t)| {
+                    id != tx && t.state == TransactionState::Disputed && t.currency == currency
+                })
+                .try_fold(Amount::ZERO, |sum, (_, t)| {
+                    sum.checked_add(t.disputed_effect())
+                });
+            if others != Some(held) {
+                return Err(TxError::HeldMismatch);
+            }
+        }
+
+        Ok(held)
+    }
+
+    /// Processes fee or interest request.
+    /// Applied regardless of available funds, also on locked account.
+    fn process_adjustment(
+        &mut self,
+        tx: &TransactionRequset,
+        config: &Config,
+    ) -> Result<(), TxError> {
+        if !config.allow_adjustments {
+            return Err(TxError::AdjustmentNotAllowed);
+        }
+        if self.tx_history.contains_key(&tx.tx) {
+            return Err(TxError::DuplicateTx(tx.tx));
+        }
+
+        let amount = Self::validate_amount(tx, config)?;
+        let adjustment = Transatcion {
+            tx_type: tx.r#type,
+            amount,
+            state: TransactionState::Normal,
+            disputed: Amount::ZERO,
+            currency: tx.currency,
+            fee: Amount::ZERO,
+        };
+
+        let total = self
+            .account
+            .total
+            .checked_add(adjustment.balance_effect())
+            .ok_or(TxError::Overflow)?;
+        self.set_balances(total, self.account.held)?;
+        self.tx_history.insert(tx.tx, adjustment);
+
+        Ok(())
+    }
+
+    /// Processes close request. Remaining total is recorded as withdrawn,
+    /// so it's not lost from history, and account is locked.
+    /// Applied also on locked account.
+    fn process_close(&mut self, tx: &TransactionRequset) -> Result<(), TxError> {
+        if self.account.held != Amount::ZERO {
+            return Err(TxError::FundsHeld);
+        }
+        if self.tx_history.contains_key(&tx.tx) {
+            return Err(TxError::DuplicateTx(tx.tx));
+        }
+
+        self.tx_history.insert(
+            tx.tx,
+            Transatcion {
+                tx_type: tx.r#type,
+                amount: self.account.total,
+                state: TransactionState::Normal,
+                disputed: Amount::ZERO,
+                currency: tx.currency,
+                fee: Amount::ZERO,
+            },
+        );
+        self.account.total = Amount::ZERO;
+        self.freeze_reason = self.freeze_reason.max(FreezeReason::Administrative);
+
+        Ok(())
+    }
+
+    /// Processes unfreeze request
+    fn process_unfreeze(&mut self, config: &Config) -> Result<(), TxError> {
+        match self.freeze_reason {
+            FreezeReason::None => return Err(TxError::NotFrozen),
+            FreezeReason::Chargeback if !config.allow_unfreeze => {
+                return Err(TxError::UnfreezeNotAllowed)
+            }
+            _ => {}
         }
+
+        self.freeze_reason = FreezeReason::None;
+        Ok(())
     }
 }
 
@@ -186,6 +1248,169 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    fn amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_amount_parse() {
+        assert_eq!(amount("1.23"), Amount::from_raw(12300));
+        assert_eq!(amount("0.0001"), Amount::from_raw(1));
+        assert_eq!(amount("-2"), Amount::from_raw(-20000));
+        assert_eq!(amount(".5"), Amount::from_raw(5000));
+        assert_eq!(
+            amount("100000000.0001"),
+            Amount::from_raw(1_000_000_000_001)
+        );
+
+        assert_eq!(amount("1,234.5000"), amount("1234.5"));
+        assert_eq!(amount("-12,345,678"), amount("-12345678"));
+        assert_eq!(amount("1.5e3"), amount("1500"));
+        assert_eq!(amount("25E-3"), amount("0.025"));
+        assert_eq!(amount("1.23456e1"), amount("12.3456"));
+
+        assert!("".parse::<Amount>().is_err());
+        assert!("1.2.3".parse::<Amount>().is_err());
+        assert!("1,23.5".parse::<Amount>().is_err());
+        assert!("1234,567".parse::<Amount>().is_err());
+        assert!("1.234,5".parse::<Amount>().is_err());
+        assert!("1e".parse::<Amount>().is_err());
+        assert!("1e99".parse::<Amount>().is_err());
+        assert!("abc".parse::<Amount>().is_err());
+        assert!("99999999999999999999".parse::<Amount>().is_err());
+    }
+
+    #[test]
+    fn test_amount_parse_localized() {
+        let parse = |s| {
+            Amount::parse_localized(
+                s,
+                AMOUNT_DECIMALS,
+                RoundingMode::default(),
+                DecimalSeparator::Comma,
+            )
+        };
+        assert_eq!(parse("1,50"), Ok(amount("1.5")));
+        assert_eq!(parse("-1.234,5"), Ok(amount("-1234.5")));
+        assert!(parse("1.50").is_err());
+        assert!(parse("1,2,3").is_err());
+    }
+
+    #[test]
+    fn test_amount_parse_rounding() {
+        let parse = |s, mode| Amount::parse(s, mode).unwrap();
+
+        assert_eq!(parse("1.00005", RoundingMode::HalfUp), amount("1.0001"));
+        assert_eq!(parse("1.00005", RoundingMode::HalfEven), amount("1.0"));
+        assert_eq!(parse("1.00005", RoundingMode::Truncate), amount("1.0"));
+
+        assert_eq!(parse("1.00015", RoundingMode::HalfEven), amount("1.0002"));
+        assert_eq!(parse("1.000051", RoundingMode::HalfEven), amount("1.0001"));
+        assert_eq!(parse("1.00009", RoundingMode::Truncate), amount("1.0"));
+        assert_eq!(parse("-1.00005", RoundingMode::HalfUp), amount("-1.0001"));
+        assert_eq!(parse("-1.00005", RoundingMode::HalfEven), amount("-1.0"));
+
+        // Default is banker's rounding
+        assert_eq!(amount("1.23455"), amount("1.2346"));
+        assert_eq!(amount("1.23465"), amount("1.2346"));
+        assert_eq!(amount("1.23456"), amount("1.2346"));
+
+        assert_eq!(
+            Amount::parse("1.23456", RoundingMode::Reject).unwrap_err(),
+            "Amount \"1.23456\" has more than 4 decimal places"
+        );
+        assert_eq!(parse("1.234500", RoundingMode::Reject), amount("1.2345"));
+
+        let scaled = |s, mode| Amount::parse_scaled(s, 2, mode).unwrap();
+        assert_eq!(scaled("1.125", RoundingMode::HalfEven), amount("1.12"));
+        assert_eq!(scaled("1.135", RoundingMode::HalfEven), amount("1.14"));
+        assert_eq!(scaled("-1.125", RoundingMode::HalfUp), amount("-1.13"));
+        assert_eq!(scaled("1.1299", RoundingMode::Truncate), amount("1.12"));
+        assert_eq!(
+            Amount::parse_scaled("1.0", 5, RoundingMode::HalfEven).unwrap_err(),
+            "Decimals 5 exceed supported 4"
+        );
+    }
+
+    #[test]
+    fn test_amount_parse_non_finite() {
+        for s in &["NaN", "nan", "inf", "-inf", "+inf", "infinity", "1e400"] {
+            assert!(s.parse::<Amount>().is_err(), "{} accepted", s);
+        }
+    }
+
+    #[test]
+    fn test_amount_from_minor_units() {
+        assert_eq!(Amount::from_minor_units(150, 2), Some(amount("1.5")));
+        assert_eq!(Amount::from_minor_units(-7, 4), Some(amount("-0.0007")));
+        assert_eq!(Amount::from_minor_units(1, 5), None);
+        assert_eq!(Amount::from_minor_units(i64::MAX, 2), None);
+    }
+
+    #[test]
+    fn test_amount_display() {
+        assert_eq!(amount("1.23").to_string(), "1.2300");
+        assert_eq!(amount("0").to_string(), "0.0000");
+        assert_eq!(amount("-0.5").to_string(), "-0.5000");
+        assert_eq!(amount("100000000.001").to_string(), "100000000.0010");
+    }
+
+    #[test]
+    fn test_amount_display_precision() {
+        assert_eq!(amount("1.2345").display(2).to_string(), "1.23");
+        assert_eq!(amount("1.235").display(2).to_string(), "1.24");
+        assert_eq!(amount("-1.235").display(2).to_string(), "-1.24");
+        assert_eq!(amount("-0.0001").display(2).to_string(), "0.00");
+        assert_eq!(amount("9.9999").display(0).to_string(), "10");
+        assert_eq!(amount("1.2345").display(4).to_string(), "1.2345");
+        assert_eq!(amount("1.2345").display(6).to_string(), "1.234500");
+    }
+
+    #[test]
+    fn test_amount_display_trimmed() {
+        assert_eq!(amount("5.0").display(4).to_string(), "5.0000");
+        assert_eq!(amount("5.0").display(4).trimmed(true).to_string(), "5");
+        assert_eq!(amount("-1.2").display(4).trimmed(true).to_string(), "-1.2");
+        assert_eq!(
+            amount("0.0304").display(4).trimmed(true).to_string(),
+            "0.0304"
+        );
+        assert_eq!(
+            amount("0.0304").display(2).trimmed(true).to_string(),
+            "0.03"
+        );
+    }
+
+    #[test]
+    fn test_amount_no_drift() {
+        let step = amount("0.1");
+        let mut sum = Amount::ZERO;
+        for _ in 0..10_000 {
+            sum = sum.checked_add(step).unwrap();
+        }
+
+        assert_eq!(sum, amount("1000.0000"));
+        assert_eq!(sum.to_string(), "1000.0000");
+    }
+
+    #[test]
+    fn test_display() {
+        let mut user = User::new(7);
+        user.account.total = amount("5.5");
+        user.account.held = amount("1.25");
+        assert_eq!(
+            user.to_string(),
+            "client 7: available 4.2500, held 1.2500, total 5.5000"
+        );
+
+        user.freeze_reason = FreezeReason::Administrative;
+        assert_eq!(
+            user.to_string(),
+            "client 7: available 4.2500, held 1.2500, total 5.5000, locked"
+        );
+        assert_eq!(format!("{:?}", amount("-0.5")), "Amount(-0.5000)");
+    }
+
     #[test]
     fn test_process_deposit() {
         let mut user = User::default();
@@ -193,34 +1418,100 @@ mod tests {
             r#type: TransactionType::Deposit,
             client: 0,
             tx: 1,
-            amount: Some(1.23),
+            amount: Some(amount("1.23")),
+            timestamp: None,
+            currency: None,
         };
 
-        assert!(user.process_deposit(tx.clone()).is_ok());
+        assert!(user.process_deposit(&tx, &Config::default()).is_ok());
 
-        assert_eq!(user.account.total, 1.23);
-        assert_eq!(user.account.avalible(), 1.23);
-        assert_eq!(user.account.held, 0.0);
+        assert_eq!(user.account.total, amount("1.23"));
+        assert_eq!(user.account.avalible(), amount("1.23"));
+        assert_eq!(user.account.held, amount("0.0"));
 
         // Doubled tx id
-        assert!(user.process_deposit(tx.clone()).is_err());
+        assert!(user.process_deposit(&tx, &Config::default()).is_err());
 
         // Missing amount
         tx.tx = 2;
         tx.amount = None;
-        assert!(user.process_deposit(tx).is_err());
+        assert!(user.process_deposit(&tx, &Config::default()).is_err());
+
+        // Negative amount
+        tx.tx = 3;
+        tx.amount = Some(amount("-1.0"));
+        assert_eq!(
+            user.process_deposit(&tx, &Config::default()).unwrap_err(),
+            TxError::InvalidAmount
+        );
+
+        assert_eq!(user.account.total, amount("1.23"));
+        assert_eq!(user.account.avalible(), amount("1.23"));
+        assert_eq!(user.account.held, amount("0.0"));
+    }
 
-        assert_eq!(user.account.total, 1.23);
-        assert_eq!(user.account.avalible(), 1.23);
-        assert_eq!(user.account.held, 0.0);
+    #[test]
+    fn test_process_deposit_max_amount() {
+        let mut user = User::default();
+        let config = Config::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("1000000000.0001")),
+            timestamp: None,
+            currency: None,
+        };
+        assert_eq!(
+            user.process_deposit(&tx, &config).unwrap_err(),
+            TxError::AmountTooLarge
+        );
+        tx.r#type = TransactionType::Withdrawal;
+        assert_eq!(
+            user.process_withdrawal(&tx, &config).unwrap_err(),
+            TxError::AmountTooLarge
+        );
+        assert_eq!(user.account.total, amount("0.0"));
+        assert!(user.tx_history.is_empty());
+
+        tx.r#type = TransactionType::Deposit;
+        tx.amount = Some(amount("1000000000"));
+        assert!(user.process_deposit(&tx, &config).is_ok());
+        assert_eq!(user.account.total, amount("1000000000"));
+    }
+
+    #[test]
+    fn test_process_deposit_min_amount() {
+        let mut user = User::default();
+        let config = Config {
+            min_amount: amount("0.01"),
+            ..Default::default()
+        };
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("0.005")),
+            timestamp: None,
+            currency: None,
+        };
+        assert_eq!(
+            user.process_deposit(&tx, &config).unwrap_err(),
+            TxError::AmountTooSmall
+        );
+        assert!(user.tx_history.is_empty());
+
+        tx.amount = Some(amount("0.01"));
+        assert!(user.process_deposit(&tx, &config).is_ok());
+        assert_eq!(user.account.total, amount("0.01"));
     }
 
     #[test]
     fn test_process_withdrawal() {
         let mut user = User {
             account: Account {
-                total: 15.0,
-                held: 5.0,
+                total: amount("15.0"),
+                held: amount("5.0"),
             },
             ..Default::default()
         };
@@ -228,31 +1519,155 @@ mod tests {
             r#type: TransactionType::Withdrawal,
             client: 0,
             tx: 1,
-            amount: Some(5.0),
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
         };
 
-        assert!(user.process_withdrawal(tx.clone()).is_ok());
+        assert!(user.process_withdrawal(&tx, &Config::default()).is_ok());
 
-        assert_eq!(user.account.total, 10.0);
-        assert_eq!(user.account.avalible(), 5.0);
-        assert_eq!(user.account.held, 5.0);
+        assert_eq!(user.account.total, amount("10.0"));
+        assert_eq!(user.account.avalible(), amount("5.0"));
+        assert_eq!(user.account.held, amount("5.0"));
 
         // Doubled tx id
-        assert!(user.process_withdrawal(tx.clone()).is_err());
+        assert_eq!(
+            user.process_withdrawal(&tx, &Config::default())
+                .unwrap_err(),
+            TxError::DuplicateTx(1)
+        );
 
         // Missing amount
         tx.tx = 2;
         tx.amount = None;
-        assert!(user.process_withdrawal(tx.clone()).is_err());
+        assert_eq!(
+            user.process_withdrawal(&tx, &Config::default())
+                .unwrap_err(),
+            TxError::InvalidAmount
+        );
+
+        // Negative amount
+        tx.tx = 4;
+        tx.amount = Some(amount("-1.0"));
+        assert_eq!(
+            user.process_withdrawal(&tx, &Config::default())
+                .unwrap_err(),
+            TxError::InvalidAmount
+        );
 
         // Out of avalible funds
         tx.tx = 3;
-        tx.amount = Some(7.0);
-        assert!(user.process_withdrawal(tx).is_err());
+        tx.amount = Some(amount("7.0"));
+        let err = user
+            .process_withdrawal(&tx, &Config::default())
+            .unwrap_err();
+        assert!(matches!(err, TxError::InsufficientFunds));
+
+        assert_eq!(user.account.total, amount("10.0"));
+        assert_eq!(user.account.avalible(), amount("5.0"));
+        assert_eq!(user.account.held, amount("5.0"));
+    }
+
+    #[test]
+    fn test_process_withdrawal_exact_balance() {
+        let mut user = User::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &Config::default()).is_ok());
+
+        tx.r#type = TransactionType::Withdrawal;
+        tx.tx = 2;
+        assert!(user.process_tx(&tx, &Config::default()).is_ok());
+        assert_eq!(user.account.total, amount("0.0"));
+
+        tx.tx = 3;
+        tx.amount = Some(amount("0.0001"));
+        assert_eq!(
+            user.process_tx(&tx, &Config::default()).unwrap_err(),
+            TxError::InsufficientFunds
+        );
+    }
+
+    #[test]
+    fn test_process_withdrawal_fee() {
+        let mut user = User::default();
+        let config = Config {
+            withdrawal_fee_bps: 100,
+            ..Default::default()
+        };
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("101.5")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &config).is_ok());
+
+        tx.r#type = TransactionType::Withdrawal;
+        tx.tx = 2;
+        tx.amount = Some(amount("100.0"));
+        assert!(user.process_tx(&tx, &config).is_ok());
+        assert_eq!(user.account.total, amount("0.5"));
+        assert_eq!(user.tx_history[&2].fee, amount("1.0"));
+        assert_eq!(user.tx_history[&2].balance_effect(), amount("-101.0"));
+
+        // Fee counts towards available funds
+        tx.tx = 3;
+        tx.amount = Some(amount("0.5"));
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::InsufficientFunds
+        );
+
+        // Dispute returns withdrawn amount, but not the fee
+        tx.tx = 2;
+        tx.amount = None;
+        for r#type in [TransactionType::Dispute, TransactionType::Chargeback].iter() {
+            tx.r#type = *r#type;
+            assert!(user.process_tx(&tx, &config).is_ok());
+        }
+        assert_eq!(user.account.total, amount("100.5"));
+        assert_eq!(amount("0.0333").basis_points(50), Some(amount("0.0002")));
+    }
+
+    #[test]
+    fn test_process_withdrawal_overdraft() {
+        let mut user = User {
+            overdraft_limit: amount("10.0"),
+            ..Default::default()
+        };
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &Config::default()).is_ok());
 
-        assert_eq!(user.account.total, 10.0);
-        assert_eq!(user.account.avalible(), 5.0);
-        assert_eq!(user.account.held, 5.0);
+        tx.r#type = TransactionType::Withdrawal;
+        tx.tx = 2;
+        tx.amount = Some(amount("15.0"));
+        assert!(user.process_tx(&tx, &Config::default()).is_ok());
+        assert_eq!(user.account.total, amount("-10.0"));
+        assert_eq!(user.account.avalible(), amount("-10.0"));
+
+        tx.tx = 3;
+        tx.amount = Some(amount("0.01"));
+        assert_eq!(
+            user.process_tx(&tx, &Config::default()).unwrap_err(),
+            TxError::InsufficientFunds
+        );
+        assert_eq!(user.account.avalible(), amount("-10.0"));
     }
 
     #[test]
@@ -262,31 +1677,100 @@ mod tests {
             r#type: TransactionType::Deposit,
             client: 0,
             tx: 1,
-            amount: Some(5.0),
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
         };
 
-        assert!(user.process_deposit(tx.clone()).is_ok());
+        assert!(user.process_deposit(&tx, &Config::default()).is_ok());
         tx.tx = 2;
-        assert!(user.process_deposit(tx.clone()).is_ok());
+        assert!(user.process_deposit(&tx, &Config::default()).is_ok());
 
         tx.r#type = TransactionType::Dispute;
-        tx.amount = Some(20.22); // Amount should be ignored anyway
-        assert_eq!(user.account.total, 10.0);
-        assert_eq!(user.account.avalible(), 10.0);
-        assert_eq!(user.account.held, 0.0);
+        tx.amount = Some(amount("20.22")); // Capped at transaction amount
+        assert_eq!(user.account.total, amount("10.0"));
+        assert_eq!(user.account.avalible(), amount("10.0"));
+        assert_eq!(user.account.held, amount("0.0"));
 
-        assert!(user.process_dispute(tx.clone()).is_ok());
+        assert!(user.process_dispute(&tx, &Config::default()).is_ok());
 
-        assert_eq!(user.account.total, 10.0);
-        assert_eq!(user.account.avalible(), 5.0);
-        assert_eq!(user.account.held, 5.0);
+        assert_eq!(user.account.total, amount("10.0"));
+        assert_eq!(user.account.avalible(), amount("5.0"));
+        assert_eq!(user.account.held, amount("5.0"));
 
-        // Doubled tx id
-        assert!(user.process_dispute(tx).is_err());
+        // Already in dispute
+        assert_eq!(
+            user.process_dispute(&tx, &Config::default()).unwrap_err(),
+            TxError::AlreadyDisputed(2)
+        );
+
+        assert_eq!(user.account.total, amount("10.0"));
+        assert_eq!(user.account.avalible(), amount("5.0"));
+        assert_eq!(user.account.held, amount("5.0"));
+    }
+
+    #[test]
+    fn test_recompute_account() {
+        let mut user = User::default();
+        let config = Config::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.tx = 2;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.r#type = TransactionType::Withdrawal;
+        tx.tx = 3;
+        tx.amount = Some(amount("1.5"));
+        assert!(user.process_tx(&tx, &config).is_ok());
 
-        assert_eq!(user.account.total, 10.0);
-        assert_eq!(user.account.avalible(), 5.0);
-        assert_eq!(user.account.held, 5.0);
+        tx.r#type = TransactionType::Dispute;
+        tx.amount = Some(amount("1.0"));
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.tx = 1;
+        tx.amount = None;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.r#type = TransactionType::Chargeback;
+        assert!(user.process_tx(&tx, &config).is_ok());
+
+        assert_eq!(user.recompute_account(), Some(user.account.clone()));
+        assert_eq!(user.account.total, amount("3.5"));
+        assert_eq!(user.account.held, amount("-1.0"));
+    }
+
+    #[test]
+    fn test_max_held() {
+        let mut user = User::default();
+        let config = Config::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.tx = 2;
+        tx.amount = Some(amount("3.0"));
+        assert!(user.process_tx(&tx, &config).is_ok());
+
+        tx.r#type = TransactionType::Dispute;
+        tx.amount = None;
+        for id in 1..=2 {
+            tx.tx = id;
+            assert!(user.process_tx(&tx, &config).is_ok());
+        }
+        tx.r#type = TransactionType::Resolve;
+        assert!(user.process_tx(&tx, &config).is_ok());
+
+        assert_eq!(user.account.held, amount("5.0"));
+        assert_eq!(user.max_held, amount("8.0"));
     }
 
     #[test]
@@ -296,62 +1780,854 @@ mod tests {
             r#type: TransactionType::Deposit,
             client: 0,
             tx: 1,
-            amount: Some(5.0),
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
         };
 
-        assert!(user.process_deposit(tx.clone()).is_ok());
+        assert!(user.process_deposit(&tx, &Config::default()).is_ok());
         tx.tx = 2;
-        assert!(user.process_deposit(tx.clone()).is_ok());
+        assert!(user.process_deposit(&tx, &Config::default()).is_ok());
 
         tx.r#type = TransactionType::Dispute;
-        assert!(user.process_dispute(tx.clone()).is_ok());
-        assert!(user.process_resolve(tx.clone()).is_ok());
+        assert!(user.process_dispute(&tx, &Config::default()).is_ok());
+        assert!(user.process_resolve(&tx, &Config::default()).is_ok());
 
-        assert_eq!(user.account.total, 10.0);
-        assert_eq!(user.account.avalible(), 10.0);
-        assert_eq!(user.account.held, 0.0);
+        assert_eq!(user.account.total, amount("10.0"));
+        assert_eq!(user.account.avalible(), amount("10.0"));
+        assert_eq!(user.account.held, amount("0.0"));
 
         // Not in despute anymore
-        assert!(user.process_resolve(tx.clone()).is_err());
+        assert!(user.process_resolve(&tx, &Config::default()).is_err());
 
-        assert_eq!(user.account.total, 10.0);
-        assert_eq!(user.account.avalible(), 10.0);
-        assert_eq!(user.account.held, 0.0);
+        assert_eq!(user.account.total, amount("10.0"));
+        assert_eq!(user.account.avalible(), amount("10.0"));
+        assert_eq!(user.account.held, amount("0.0"));
     }
 
     #[test]
-    fn test_process_chargeback() {
+    fn test_strict_amounts() {
         let mut user = User::default();
+        let mut config = Config {
+            strict_amounts: true,
+            ..Default::default()
+        };
         let mut tx = TransactionRequset {
             r#type: TransactionType::Deposit,
             client: 0,
             tx: 1,
-            amount: Some(5.0),
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
         };
+        assert!(user.process_tx(&tx, &config).is_ok());
 
-        assert!(user.process_deposit(tx.clone()).is_ok());
-        tx.tx = 2;
-        assert!(user.process_deposit(tx.clone()).is_ok());
+        tx.r#type = TransactionType::Dispute;
+        tx.amount = Some(amount("1.0"));
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::InvalidAmount
+        );
+        assert_eq!(user.account.held, amount("0.0"));
+
+        tx.amount = None;
+        assert!(user.process_tx(&tx, &config).is_ok());
+
+        // Lenient mode ignores amount of resolve
+        config.strict_amounts = false;
+        tx.r#type = TransactionType::Resolve;
+        tx.amount = Some(amount("1.0"));
+        assert!(user.process_tx(&tx, &config).is_ok());
+    }
+
+    #[test]
+    fn test_process_partial_dispute() {
+        let mut user = User::default();
+        let config = Config::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_deposit(&tx, &config).is_ok());
 
         tx.r#type = TransactionType::Dispute;
-        assert!(user.process_dispute(tx.clone()).is_ok());
-        assert!(user.process_chargeback(tx.clone()).is_ok());
+        tx.amount = Some(amount("-1.0"));
+        assert_eq!(
+            user.process_dispute(&tx, &config).unwrap_err(),
+            TxError::InvalidAmount
+        );
 
-        assert_eq!(user.account.total, 5.0);
-        assert_eq!(user.account.avalible(), 5.0);
-        assert_eq!(user.account.held, 0.0);
-        assert!(user.frozen);
+        tx.amount = Some(amount("3.0"));
+        assert!(user.process_dispute(&tx, &config).is_ok());
+        assert_eq!(user.account.held, amount("3.0"));
+        assert_eq!(user.account.avalible(), amount("2.0"));
 
-        // Not in despute anymore
-        assert!(user.process_chargeback(tx.clone()).is_err());
+        tx.amount = None;
+        assert!(user.process_chargeback(&tx, &Config::default()).is_ok());
+        assert_eq!(user.account.held, amount("0.0"));
+        assert_eq!(user.account.total, amount("2.0"));
+    }
 
-        // Account is locked
-        tx.r#type = TransactionType::Withdrawal;
-        tx.tx = 10;
-        assert!(user.process_tx(tx.clone()).is_err());
+    #[test]
+    fn test_process_redispute() {
+        let mut user = User::default();
+        let mut config = Config::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_deposit(&tx, &Config::default()).is_ok());
+
+        tx.r#type = TransactionType::Dispute;
+        assert!(user.process_dispute(&tx, &config).is_ok());
+        assert!(user.process_resolve(&tx, &Config::default()).is_ok());
+        assert_eq!(user.tx_history[&1].state, TransactionState::Resolved);
+
+        // Allowed by default
+        assert!(user.process_dispute(&tx, &config).is_ok());
+        assert!(user.process_resolve(&tx, &Config::default()).is_ok());
+
+        config.allow_redispute = false;
+        assert_eq!(
+            user.process_dispute(&tx, &config).unwrap_err(),
+            TxError::NotDisputable(1)
+        );
+        assert_eq!(user.account.held, amount("0.0"));
+        assert_eq!(user.account.avalible(), amount("5.0"));
+    }
 
-        assert_eq!(user.account.total, 5.0);
-        assert_eq!(user.account.avalible(), 5.0);
-        assert_eq!(user.account.held, 0.0);
+    #[test]
+    fn test_resolve_chargeback_errors() {
+        let mut user = User::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_deposit(&tx, &Config::default()).is_ok());
+        tx.amount = None;
+
+        // Existing transaction which is not disputed
+        assert_eq!(
+            user.process_resolve(&tx, &Config::default()).unwrap_err(),
+            TxError::NotDisputed(1)
+        );
+        assert_eq!(
+            user.process_chargeback(&tx, &Config::default())
+                .unwrap_err(),
+            TxError::NotDisputed(1)
+        );
+
+        tx.tx = 2;
+        assert_eq!(
+            user.process_resolve(&tx, &Config::default()).unwrap_err(),
+            TxError::UnknownTx(2)
+        );
+        assert_eq!(
+            user.process_chargeback(&tx, &Config::default())
+                .unwrap_err(),
+            TxError::UnknownTx(2)
+        );
+        assert!(!user.frozen());
+    }
+
+    #[test]
+    fn test_can_transition() {
+        use TransactionState::*;
+
+        let config = Config::default();
+        let mut old_tx = Transatcion {
+            tx_type: TransactionType::Deposit,
+            amount: amount("5.0"),
+            state: Normal,
+            disputed: Amount::ZERO,
+            currency: None,
+            fee: Amount::ZERO,
+        };
+        let states = [Normal, Disputed, Resolved, Chargedback];
+        let allowed = |t: &Transatcion| {
+            states
+                .iter()
+                .copied()
+                .filter(|&to| t.can_transition(to, &config))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(allowed(&old_tx), vec![Disputed]);
+        old_tx.state = Disputed;
+        assert_eq!(allowed(&old_tx), vec![Resolved, Chargedback]);
+        old_tx.state = Resolved;
+        assert_eq!(allowed(&old_tx), vec![Disputed]);
+        old_tx.state = Chargedback;
+        assert_eq!(allowed(&old_tx), vec![]);
+    }
+
+    #[test]
+    fn test_resolve_chargeback_order() {
+        let config = Config::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        let mut resolved = User::default();
+        assert!(resolved.process_deposit(&tx, &config).is_ok());
+        tx.amount = None;
+        assert!(resolved.process_dispute(&tx, &config).is_ok());
+        let mut charged_back = resolved.clone();
+
+        // Resolve wins, chargeback is rejected
+        assert!(resolved.process_resolve(&tx, &config).is_ok());
+        assert_eq!(
+            resolved.process_chargeback(&tx, &config).unwrap_err(),
+            TxError::NotDisputed(1)
+        );
+        assert_eq!(resolved.tx_history[&1].state, TransactionState::Resolved);
+        assert_eq!(resolved.account.total, amount("5.0"));
+        assert!(!resolved.frozen());
+
+        // Chargeback wins, resolve is rejected
+        assert!(charged_back.process_chargeback(&tx, &config).is_ok());
+        assert_eq!(
+            charged_back.process_resolve(&tx, &config).unwrap_err(),
+            TxError::NotDisputed(1)
+        );
+        assert_eq!(
+            charged_back.tx_history[&1].state,
+            TransactionState::Chargedback
+        );
+        assert_eq!(charged_back.account.total, amount("0.0"));
+        assert!(charged_back.frozen());
+    }
+
+    #[test]
+    fn test_process_chargeback() {
+        let mut user = User::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+
+        assert!(user.process_deposit(&tx, &Config::default()).is_ok());
+        tx.tx = 2;
+        assert!(user.process_deposit(&tx, &Config::default()).is_ok());
+
+        tx.r#type = TransactionType::Dispute;
+        assert!(user.process_dispute(&tx, &Config::default()).is_ok());
+        assert!(user.process_chargeback(&tx, &Config::default()).is_ok());
+
+        assert_eq!(user.account.total, amount("5.0"));
+        assert_eq!(user.account.avalible(), amount("5.0"));
+        assert_eq!(user.account.held, amount("0.0"));
+        assert!(user.frozen());
+
+        // Not in despute anymore
+        assert!(user.process_chargeback(&tx, &Config::default()).is_err());
+
+        // Account is locked
+        tx.r#type = TransactionType::Withdrawal;
+        tx.tx = 10;
+        assert!(user.process_tx(&tx, &Config::default()).is_err());
+
+        assert_eq!(user.account.total, amount("5.0"));
+        assert_eq!(user.account.avalible(), amount("5.0"));
+        assert_eq!(user.account.held, amount("0.0"));
+    }
+
+    #[test]
+    fn test_history_eviction() {
+        let mut user = User::default();
+        let config = Config {
+            evict_chargedback: true,
+            history_window: Some(2),
+            ..Default::default()
+        };
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        for id in 1..=3 {
+            tx.tx = id;
+            assert!(user.process_tx(&tx, &config).is_ok());
+        }
+        assert_eq!(user.tx_history.len(), 2);
+
+        tx.r#type = TransactionType::Dispute;
+        tx.amount = None;
+        tx.tx = 1;
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::UnknownTx(1)
+        );
+
+        // Disputed transaction outlives the window
+        tx.tx = 2;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.r#type = TransactionType::Deposit;
+        tx.amount = Some(amount("5.0"));
+        for id in 4..=10 {
+            tx.tx = id;
+            assert!(user.process_tx(&tx, &config).is_ok());
+        }
+        assert_eq!(user.tx_history.len(), 2);
+        assert!(user.tx_history.contains_key(&2));
+
+        tx.r#type = TransactionType::Chargeback;
+        tx.amount = None;
+        tx.tx = 2;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        assert_eq!(user.tx_history.len(), 1);
+        assert_eq!(user.account.total, amount("45.0"));
+
+        tx.r#type = TransactionType::Dispute;
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::UnknownTx(2)
+        );
+    }
+
+    #[test]
+    fn test_frozen_account_disputes() {
+        let mut user = User::default();
+        let config = Config::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.r#type = TransactionType::Dispute;
+        tx.amount = None;
+        assert!(user.process_tx(&tx, &config).is_ok());
+
+        tx.r#type = TransactionType::Freeze;
+        assert!(user.process_tx(&tx, &config).is_ok());
+
+        tx.r#type = TransactionType::Resolve;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        assert_eq!(user.account.held, amount("0.0"));
+        assert_eq!(user.account.avalible(), amount("5.0"));
+
+        tx.r#type = TransactionType::Withdrawal;
+        tx.tx = 2;
+        tx.amount = Some(amount("1.0"));
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::AccountFrozen
+        );
+        tx.r#type = TransactionType::Deposit;
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::AccountFrozen
+        );
+    }
+
+    #[test]
+    fn test_record_frozen_rejections() {
+        let mut user = User::default();
+        let config = Config {
+            record_frozen_rejections: true,
+            ..Default::default()
+        };
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Freeze,
+            client: 0,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &config).is_ok());
+
+        tx.r#type = TransactionType::Deposit;
+        tx.amount = Some(amount("5.0"));
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::AccountFrozen
+        );
+        assert_eq!(user.tx_history[&1].state, TransactionState::Rejected);
+        assert_eq!(user.tx_history[&1].amount, amount("5.0"));
+        assert_eq!(user.account, Account::default());
+        assert_eq!(user.recompute_account(), Some(Account::default()));
+
+        // Recorded request can't be disputed
+        tx.r#type = TransactionType::Dispute;
+        tx.amount = None;
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::NotDisputable(1)
+        );
+    }
+
+    #[test]
+    fn test_process_adjustment() {
+        let mut user = User::default();
+        let mut config = Config::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &config).is_ok());
+
+        tx.r#type = TransactionType::Fee;
+        tx.tx = 2;
+        tx.amount = Some(amount("7.5"));
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::AdjustmentNotAllowed
+        );
+
+        // Fee may exceed available funds
+        config.allow_adjustments = true;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        assert_eq!(user.account.total, amount("-2.5"));
+        assert!(!user.tx_history[&2].is_disputable(&config));
+
+        tx.r#type = TransactionType::Interest;
+        tx.tx = 3;
+        tx.amount = Some(amount("0.5"));
+        assert!(user.process_tx(&tx, &config).is_ok());
+        assert_eq!(user.account.total, amount("-2.0"));
+
+        tx.r#type = TransactionType::Dispute;
+        tx.tx = 2;
+        tx.amount = None;
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::NotDisputable(2)
+        );
+    }
+
+    #[test]
+    fn test_process_close() {
+        let mut user = User::default();
+        let config = Config::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.r#type = TransactionType::Dispute;
+        tx.amount = None;
+        assert!(user.process_tx(&tx, &config).is_ok());
+
+        tx.r#type = TransactionType::Close;
+        tx.tx = 2;
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::FundsHeld
+        );
+        assert!(!user.frozen());
+
+        tx.r#type = TransactionType::Resolve;
+        tx.tx = 1;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.r#type = TransactionType::Close;
+        tx.tx = 2;
+        assert!(user.process_tx(&tx, &config).is_ok());
+
+        assert_eq!(user.account, Account::default());
+        assert_eq!(user.freeze_reason, FreezeReason::Administrative);
+        assert_eq!(user.tx_history[&2].amount, amount("5.0"));
+        assert_eq!(user.recompute_account(), Some(user.account.clone()));
+    }
+
+    #[test]
+    fn test_chargeback_without_freeze() {
+        let mut user = User::default();
+        let config = Config {
+            freeze_on_chargeback: false,
+            ..Default::default()
+        };
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("5.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.r#type = TransactionType::Dispute;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.r#type = TransactionType::Chargeback;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        assert!(!user.frozen());
+        assert_eq!(user.account.total, amount("0.0"));
+
+        tx.r#type = TransactionType::Deposit;
+        tx.tx = 2;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        assert_eq!(user.account.total, amount("5.0"));
+    }
+
+    #[test]
+    fn test_process_withdrawal_dispute() {
+        let mut user = User::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("10.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &Config::default()).is_ok());
+
+        tx.r#type = TransactionType::Withdrawal;
+        tx.tx = 2;
+        tx.amount = Some(amount("4.0"));
+        assert!(user.process_tx(&tx, &Config::default()).is_ok());
+        assert_eq!(user.account.total, amount("6.0"));
+        assert_eq!(user.account.avalible(), amount("6.0"));
+
+        // Withdrawn funds are credited back while pending
+        tx.r#type = TransactionType::Dispute;
+        tx.amount = None;
+        assert!(user.process_tx(&tx, &Config::default()).is_ok());
+        assert_eq!(user.account.total, amount("6.0"));
+        assert_eq!(user.account.avalible(), amount("10.0"));
+        assert_eq!(user.account.held, amount("-4.0"));
+
+        tx.r#type = TransactionType::Resolve;
+        assert!(user.process_tx(&tx, &Config::default()).is_ok());
+        assert_eq!(user.account.total, amount("6.0"));
+        assert_eq!(user.account.avalible(), amount("6.0"));
+        assert_eq!(user.account.held, amount("0.0"));
+
+        tx.r#type = TransactionType::Dispute;
+        assert!(user.process_tx(&tx, &Config::default()).is_ok());
+        tx.r#type = TransactionType::Chargeback;
+        assert!(user.process_tx(&tx, &Config::default()).is_ok());
+        assert_eq!(user.account.total, amount("10.0"));
+        assert_eq!(user.account.avalible(), amount("10.0"));
+        assert_eq!(user.account.held, amount("0.0"));
+        assert!(user.frozen());
+    }
+
+    #[test]
+    fn test_withdrawal_reversed_once() {
+        let mut user = User::default();
+        let config = Config::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("10.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.r#type = TransactionType::Withdrawal;
+        tx.tx = 2;
+        tx.amount = Some(amount("4.0"));
+        assert!(user.process_tx(&tx, &config).is_ok());
+
+        tx.amount = None;
+        for r#type in [TransactionType::Dispute, TransactionType::Chargeback].iter() {
+            tx.r#type = *r#type;
+            assert!(user.process_tx(&tx, &config).is_ok());
+        }
+        assert_eq!(user.account.total, amount("10.0"));
+
+        // Charged back withdrawal can't be credited back again
+        tx.r#type = TransactionType::Dispute;
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::NotDisputable(2)
+        );
+        tx.r#type = TransactionType::Chargeback;
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::NotDisputed(2)
+        );
+        assert_eq!(user.account.total, amount("10.0"));
+        assert_eq!(user.account.held, amount("0.0"));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let near_max = Amount::from_raw(i64::MAX - 1);
+        let mut user = User::default();
+        user.account.total = near_max;
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("1.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert_eq!(
+            user.process_deposit(&tx, &Config::default()).unwrap_err(),
+            TxError::Overflow
+        );
+        assert_eq!(user.account.total, near_max);
+        assert!(user.tx_history.is_empty());
+
+        user.account.total = Amount::ZERO;
+        assert!(user.process_deposit(&tx, &Config::default()).is_ok());
+        user.account.total = near_max;
+        user.account.held = near_max;
+
+        tx.r#type = TransactionType::Dispute;
+        assert_eq!(
+            user.process_dispute(&tx, &Config::default()).unwrap_err(),
+            TxError::Overflow
+        );
+        assert_eq!(user.account.held, near_max);
+        assert_eq!(user.tx_history[&1].state, TransactionState::Normal);
+        assert_eq!(user.tx_history[&1].disputed, Amount::ZERO);
+    }
+
+    #[test]
+    fn test_available_overflow() {
+        let config = Config {
+            max_amount: Amount::from_raw(i64::MAX),
+            ..Default::default()
+        };
+        let mut user = User::default();
+        let requests = [
+            (TransactionType::Deposit, 1, "922337203685477"),
+            (TransactionType::Withdrawal, 2, "1.0"),
+            (TransactionType::Deposit, 3, "1.0"),
+        ];
+        for &(r#type, tx, value) in requests.iter() {
+            let tx = TransactionRequset {
+                r#type,
+                client: 0,
+                tx,
+                amount: Some(amount(value)),
+                timestamp: None,
+                currency: None,
+            };
+            assert!(user.process_tx(&tx, &config).is_ok());
+        }
+
+        // Disputed withdrawal would credit available funds past the limit
+        let tx = TransactionRequset {
+            r#type: TransactionType::Dispute,
+            client: 0,
+            tx: 2,
+            amount: None,
+            timestamp: None,
+            currency: None,
+        };
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::Overflow
+        );
+        assert_eq!(user.tx_history[&2].state, TransactionState::Normal);
+        assert_eq!(user.account.held, Amount::ZERO);
+    }
+
+    #[test]
+    fn test_invariants_large_disputes() {
+        let config = Config {
+            max_amount: Amount::from_raw(i64::MAX),
+            ..Default::default()
+        };
+        let mut user = User::default();
+        let big = "922337203685477";
+        let requests = [
+            (TransactionType::Deposit, 1, Some(big)),
+            (TransactionType::Withdrawal, 2, Some(big)),
+            (TransactionType::Deposit, 3, Some(big)),
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Dispute, 2, None),
+            // Sum of disputed deposits alone is out of range
+            (TransactionType::Dispute, 3, None),
+        ];
+        for &(r#type, tx, value) in requests.iter() {
+            let tx = TransactionRequset {
+                r#type,
+                client: 0,
+                tx,
+                amount: value.map(amount),
+                timestamp: None,
+                currency: None,
+            };
+            assert!(user.process_tx(&tx, &config).is_ok());
+        }
+        assert_eq!(user.account.held, amount(big));
+        assert_eq!(user.account.avalible(), Amount::ZERO);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "Held funds don't match disputed transactions")]
+    fn test_invariants_violation() {
+        let mut user = User::default();
+        // Disputed transaction injected without holding funds
+        user.tx_history.insert(
+            1,
+            Transatcion {
+                tx_type: TransactionType::Deposit,
+                amount: amount("5.0"),
+                state: TransactionState::Disputed,
+                disputed: amount("5.0"),
+                currency: None,
+                fee: Amount::ZERO,
+            },
+        );
+        user.account.total = amount("5.0");
+
+        let tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 2,
+            amount: Some(amount("1.0")),
+            timestamp: None,
+            currency: None,
+        };
+        let _ = user.process_tx(&tx, &Config::default());
+    }
+
+    #[test]
+    fn test_release_held_mismatch() {
+        let config = Config::default();
+        let mut user = User::default();
+        // Disputed transaction injected holding only part of its amount
+        user.tx_history.insert(
+            1,
+            Transatcion {
+                tx_type: TransactionType::Deposit,
+                amount: amount("5.0"),
+                state: TransactionState::Disputed,
+                disputed: amount("5.0"),
+                currency: None,
+                fee: Amount::ZERO,
+            },
+        );
+        user.account = Account {
+            total: amount("5.0"),
+            held: amount("2.0"),
+        };
+
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Resolve,
+            client: 0,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+        };
+        assert_eq!(
+            user.process_resolve(&tx, &config).unwrap_err(),
+            TxError::HeldMismatch
+        );
+        tx.r#type = TransactionType::Chargeback;
+        assert_eq!(
+            user.process_chargeback(&tx, &config).unwrap_err(),
+            TxError::HeldMismatch
+        );
+        assert_eq!(user.account.held, amount("2.0"));
+        assert_eq!(user.tx_history[&1].state, TransactionState::Disputed);
+        assert!(!user.frozen());
+    }
+
+    #[test]
+    fn test_process_tx_counts() {
+        let mut user = User::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Deposit,
+            client: 0,
+            tx: 1,
+            amount: Some(amount("1.0")),
+            timestamp: None,
+            currency: None,
+        };
+        assert!(user.process_tx(&tx, &Config::default()).is_ok());
+
+        tx.tx = 2;
+        tx.amount = None;
+        assert!(user.process_tx(&tx, &Config::default()).is_err());
+
+        assert_eq!(user.accepted_count, 1);
+        assert_eq!(user.rejected_count, 1);
+    }
+
+    #[test]
+    fn test_process_unfreeze() {
+        let mut user = User::default();
+        let mut config = Config::default();
+        let mut tx = TransactionRequset {
+            r#type: TransactionType::Unfreeze,
+            client: 0,
+            tx: 0,
+            amount: None,
+            timestamp: None,
+            currency: None,
+        };
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::NotFrozen
+        );
+
+        // Administrative freeze can always be reversed
+        tx.r#type = TransactionType::Freeze;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        assert!(user.frozen());
+        tx.r#type = TransactionType::Unfreeze;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        assert!(!user.frozen());
+
+        tx.r#type = TransactionType::Deposit;
+        tx.tx = 1;
+        tx.amount = Some(amount("5.0"));
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.r#type = TransactionType::Dispute;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        tx.r#type = TransactionType::Chargeback;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        assert!(user.frozen());
+
+        tx.r#type = TransactionType::Unfreeze;
+        assert_eq!(
+            user.process_tx(&tx, &config).unwrap_err(),
+            TxError::UnfreezeNotAllowed
+        );
+
+        config.allow_unfreeze = true;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        assert!(!user.frozen());
+
+        tx.r#type = TransactionType::Deposit;
+        tx.tx = 2;
+        assert!(user.process_tx(&tx, &config).is_ok());
+        assert_eq!(user.account.total, amount("5.0"));
     }
 }
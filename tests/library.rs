@@ -0,0 +1,30 @@
+use accounting::{Amount, Engine, TransactionRequset, TransactionType, TxError};
+use pretty_assertions::assert_eq;
+
+#[test]
+fn test_engine_as_library() {
+    let data = "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,1.5\ndeposit,2,3,2.0\n";
+    let mut engine = Engine::new();
+    assert!(engine.process_bytes(data.as_bytes()).unwrap().is_empty());
+
+    let tx = TransactionRequset {
+        r#type: TransactionType::Withdrawal,
+        client: 2,
+        tx: 4,
+        amount: Some(Amount::from_int(3)),
+        timestamp: None,
+        currency: None,
+    };
+    assert_eq!(
+        engine.process_tx(&tx).unwrap_err(),
+        TxError::InsufficientFunds
+    );
+
+    assert_eq!(
+        engine.report().to_csv_string(),
+        "client,available,held,total,locked
+1,3.5000,0.0000,3.5000,false
+2,2.0000,0.0000,2.0000,false
+"
+    );
+}